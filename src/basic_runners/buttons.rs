@@ -0,0 +1,75 @@
+use std::{
+    sync::mpsc::{channel, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use super::supervisor::{is_shutting_down, WorkerResult};
+use crate::service::{
+    alarm,
+    button::{ButtonClassifier, Press},
+    control,
+    database::project::{add_event, get_active_project, EventKind},
+    gpio::{get_config, watch_button},
+    webcam,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub fn entry_loop() -> WorkerResult {
+    let buttons = &get_config().buttons;
+    if buttons.is_empty() {
+        info!("no buttons configured");
+        return Ok(());
+    }
+    let (sender, receiver) = channel();
+    /* the interrupts only run as long as the pins are alive */
+    let mut pins = Vec::new();
+    for (index, button) in buttons.iter().enumerate() {
+        let sender = sender.clone();
+        pins.push(watch_button(button, move |pressed| {
+            let _ = sender.send((index, pressed, Instant::now()));
+        })?);
+    }
+    let mut classifiers: Vec<ButtonClassifier> =
+        buttons.iter().map(ButtonClassifier::new).collect();
+
+    while !is_shutting_down() {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok((index, pressed, at)) => {
+                if let Some(press) = classifiers[index].edge(pressed, at) {
+                    handle(press);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(Box::from("Button interrupts stopped"))
+            }
+        }
+        for classifier in classifiers.iter_mut() {
+            if let Some(press) = classifier.poll(Instant::now()) {
+                handle(press);
+            }
+        }
+    }
+    Ok(())
+}
+
+/* the same service calls the HTTP routes make */
+fn handle(press: Press) {
+    info!("button press: {:?}", press);
+    let result = match press {
+        Press::Short => alarm::acknowledge_all().map(|_| ()),
+        Press::Long => control::toggle_pause().map(|_| ()),
+        Press::Double => checked(),
+    };
+    if let Err(e) = result {
+        error!("Error: {}", e);
+    }
+}
+
+/* the snapshot is taken by the capture worker, nobody waits for it here */
+fn checked() -> Result<(), Box<dyn std::error::Error>> {
+    let project = get_active_project()?;
+    webcam::request_snapshot()?;
+    add_event(project.id, EventKind::Checked)
+}
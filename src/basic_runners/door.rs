@@ -0,0 +1,34 @@
+use std::time::{Duration, Instant};
+
+use super::supervisor::{sleep_unless_shutdown, WorkerResult};
+use crate::service::{
+    door,
+    gpio::{has_role, read_door, PinRole},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/* a new reed switch level has to hold this long before it counts */
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+pub fn entry_loop() -> WorkerResult {
+    if !has_role(PinRole::Door) {
+        info!("no door pin configured");
+        return Ok(());
+    }
+    let mut changed_at: Option<Instant> = None;
+    loop {
+        let open = read_door()?;
+        if open == door::is_open() {
+            changed_at = None;
+        } else {
+            let since = *changed_at.get_or_insert_with(Instant::now);
+            if since.elapsed() >= DEBOUNCE {
+                door::set_open(open)?;
+                changed_at = None;
+            }
+        }
+        if !sleep_unless_shutdown(POLL_INTERVAL) {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::{Duration, Instant};
+
+use super::supervisor::{sleep_unless_shutdown, WorkerResult};
+use crate::service::{
+    config,
+    gpio::{has_role, turn_off_led, turn_on_led, PinRole},
+    indicator::{chamber_state, led_level},
+};
+
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+const LEDS: [(u8, PinRole); 3] = [(1, PinRole::Led1), (2, PinRole::Led2), (3, PinRole::Led3)];
+
+pub fn entry_loop() -> WorkerResult {
+    let indicator = &config::get().indicator;
+    let leds: Vec<_> = LEDS
+        .iter()
+        .filter(|(_, role)| has_role(*role))
+        .map(|(index, _)| {
+            let rules = match index {
+                1 => &indicator.led1,
+                2 => &indicator.led2,
+                _ => &indicator.led3,
+            };
+            (*index, rules)
+        })
+        .collect();
+    if leds.is_empty() {
+        info!("no led pins configured, only raising alarms");
+    }
+
+    let started = Instant::now();
+    let started_at = chrono::Utc::now().timestamp() as u64;
+    let mut levels: Vec<Option<bool>> = vec![None; leds.len()];
+    loop {
+        let state = chamber_state(chrono::Utc::now().timestamp() as u64, started_at);
+        for ((index, rules), level) in leds.iter().zip(levels.iter_mut()) {
+            let on = led_level(rules, &state, started.elapsed());
+            if *level != Some(on) {
+                if on {
+                    turn_on_led(*index)?;
+                } else {
+                    turn_off_led(*index)?;
+                }
+                *level = Some(on);
+            }
+        }
+        if !sleep_unless_shutdown(TICK_INTERVAL) {
+            for (index, _) in leds.iter() {
+                turn_off_led(*index)?;
+            }
+            return Ok(());
+        }
+    }
+}
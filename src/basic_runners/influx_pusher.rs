@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use super::supervisor::{sleep_unless_shutdown, WorkerResult};
+use crate::service::influx::{get_config, is_retryable, requeue, take_batch, write_batch};
+
+const BATCH_SIZE: usize = 500;
+const PUSH_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+pub fn entry_loop() -> WorkerResult {
+    let config = match get_config() {
+        Some(config) => config,
+        None => {
+            info!("influx not configured, influx push disabled");
+            return Ok(());
+        }
+    };
+    let mut backoff = PUSH_INTERVAL;
+    loop {
+        if !sleep_unless_shutdown(backoff) {
+            return Ok(());
+        }
+        loop {
+            let batch = take_batch(BATCH_SIZE);
+            if batch.is_empty() {
+                backoff = PUSH_INTERVAL;
+                break;
+            }
+            match write_batch(config, &batch) {
+                Ok(_) => {}
+                Err(e) if !is_retryable(e.as_ref()) => {
+                    error!(
+                        "Error: dropping {} lines rejected by influx: {}",
+                        batch.len(),
+                        e
+                    );
+                }
+                Err(e) => {
+                    error!("Error: {}", e);
+                    requeue(batch);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    break;
+                }
+            }
+        }
+    }
+}
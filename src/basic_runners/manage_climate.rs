@@ -1,76 +1,146 @@
-use async_std::task;
 use pid::Pid;
-use std::{thread, time::Duration};
+use std::time::{Duration, Instant};
 
+use super::supervisor::{sleep_unless_shutdown, WorkerResult};
 use crate::service::{
-    database::{project::get_active_project, sensor::SensorData},
-    gpio::{turn_off_heating, turn_off_humidifier, turn_on_heating, turn_on_humidifier},
+    config::{self, FreshAirSchedule, PidTuning},
+    control,
+    database::{
+        project::{get_active_project, Project},
+        sensor::SensorData,
+    },
+    door,
+    gpio::{has_role, PinRole},
+    influx,
+    metrics::{self, Gauge},
+    output::{self, Actuator},
     sensor::get_sensor_data,
 };
 
-const TEMP_DUTY_CYCLE: f32 = 1.0;
-const HUM_DUTY_CYCLE: f32 = 1.0;
 const PID_LIMIT: f32 = 100.0;
+const PROJECT_POLL_INTERVAL: Duration = Duration::from_secs(10);
 
-pub fn entry_loop_hum() {
-    let project = match get_active_project() {
-        Ok(project) => project,
-        Err(e) => {
-            error!("Error: {}", e);
-            return;
-        }
-    };
-    let mut hum_pid: Pid<f32> = Pid::new(project.settings.hum, PID_LIMIT);
-    hum_pid
-        .p(35.0, PID_LIMIT)
-        .i(0.09, PID_LIMIT)
-        .d(10.0, PID_LIMIT);
-
-    let mut sensor_data: SensorData = match get_sensor_data() {
-        Ok(sensor_data) => sensor_data,
-        Err(e) => {
-            error!("Error: {}", e);
-            return;
+/* switches every actuator off when a control loop ends, also when it ends with an error, so no
+output keeps cycling at the demand of a loop that is no longer running */
+struct ZeroDemandOnExit;
+
+impl Drop for ZeroDemandOnExit {
+    fn drop(&mut self) {
+        for actuator in [
+            Actuator::Heating,
+            Actuator::Cooling,
+            Actuator::Humidifier,
+            Actuator::Fan,
+        ] {
+            if let Err(e) = output::set_demand(actuator, 0.0) {
+                error!("Error: {}", e);
+            }
         }
-    };
+    }
+}
 
-    match turn_off_humidifier() {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Error: {}", e);
+/* idle until a project is started instead of failing the worker, None on shutdown */
+fn wait_for_active_project() -> Option<Project> {
+    loop {
+        match get_active_project() {
+            Ok(project) => return Some(project),
+            Err(_) => {
+                if !sleep_unless_shutdown(PROJECT_POLL_INTERVAL) {
+                    return None;
+                }
+            }
         }
+    }
+}
+
+/* split range like the temperature loop: the humidifier PID holds the lower edge of the deadband,
+the fan PID the upper one. A fresh air purge runs the fan at full duty and holds the humidifier off */
+pub fn entry_loop_hum() -> WorkerResult {
+    let _zero_demand = ZeroDemandOnExit;
+    let project = match wait_for_active_project() {
+        Some(project) => project,
+        None => return Ok(()),
     };
+    let control = &config::get().control;
+    let (humidifier_target, fan_target) = split_targets(
+        project.settings.hum,
+        control.hum_deadband,
+        has_role(PinRole::Fan),
+    );
+    let mut hum_pid = control_pid(humidifier_target, &control.humidifier_pid);
+    let mut fan_pid = control_pid(fan_target, &control.fan_pid);
+    let mut last_purge = Instant::now();
+    let mut door_was_open = false;
+
+    let mut sensor_data: SensorData = get_sensor_data()?;
 
     loop {
-        let hum_on_percentage = hum_pid.next_control_output(sensor_data.hum).output / PID_LIMIT;
-        let hum_on_time = hum_on_percentage * HUM_DUTY_CYCLE;
-        let hum_off_time = HUM_DUTY_CYCLE - hum_on_time;
+        let door_open = door::is_open();
+        if door_open != door_was_open {
+            hold_integral(&mut hum_pid, &control.humidifier_pid, door_open);
+            hold_integral(&mut fan_pid, &control.fan_pid, door_open);
+            /* an open door already exchanged the air */
+            last_purge = Instant::now();
+            door_was_open = door_open;
+        }
+        let purging = match fresh_air_purge(&control.fresh_air, last_purge) {
+            Purge::Idle => false,
+            Purge::Running => true,
+            Purge::Done => {
+                last_purge = Instant::now();
+                false
+            }
+        };
+        let humidifying = !purging && sensor_data.hum < project.settings.hum;
+        if !door_open {
+            if humidifying {
+                fan_pid.reset_integral_term();
+            } else {
+                hum_pid.reset_integral_term();
+            }
+        }
+        let hum_output = hum_pid.next_control_output(sensor_data.hum).output;
+        let fan_output = -fan_pid.next_control_output(sensor_data.hum).output;
+        let (hum_on_percentage, fan_on_percentage) = if control::is_paused() {
+            hum_pid.reset_integral_term();
+            fan_pid.reset_integral_term();
+            (0.0, 0.0)
+        } else if door_open {
+            (0.0, 0.0)
+        } else if purging {
+            (0.0, 1.0)
+        } else if humidifying {
+            (hum_output.max(0.0) / PID_LIMIT, 0.0)
+        } else {
+            (0.0, fan_output.max(0.0) / PID_LIMIT)
+        };
         warn!(
-            "target_hum: {} current_hum: {} temp_on_hum: {}",
-            project.settings.hum, sensor_data.hum, hum_on_time
+            "target_hum: {} current_hum: {} hum_on_percentage: {} fan_on_percentage: {}",
+            project.settings.hum, sensor_data.hum, hum_on_percentage, fan_on_percentage
         );
-        task::spawn(async move {
-            if hum_on_percentage > 0.0 {
-                match turn_on_humidifier() {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Error: {}", e);
-                    }
-                };
-                task::sleep(Duration::from_secs_f32(hum_on_time)).await;
-                if hum_on_percentage < 0.995 {
-                    match turn_off_humidifier() {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error: {}", e);
-                        }
-                    };
-                }
-            }
-            task::sleep(Duration::from_secs_f32(hum_off_time)).await;
-        });
-
-        thread::sleep(std::time::Duration::from_secs_f32(HUM_DUTY_CYCLE));
+        output::set_demand(Actuator::Humidifier, hum_on_percentage)?;
+        output::set_demand(Actuator::Fan, fan_on_percentage)?;
+        metrics::set(Gauge::TargetHumidity, project.settings.hum as f64);
+        metrics::set(Gauge::HumPidOutput, hum_output as f64);
+        metrics::set(Gauge::FanPidOutput, fan_output as f64);
+        let time = chrono::Utc::now().timestamp() as u64;
+        influx::queue(influx::controller_line(
+            "humidity",
+            humidifier_target,
+            hum_output,
+            output::delivered_duty(Actuator::Humidifier)?,
+            time,
+        ));
+        influx::queue(influx::controller_line(
+            "fan",
+            fan_target,
+            fan_output,
+            output::delivered_duty(Actuator::Fan)?,
+            time,
+        ));
+        if !sleep_unless_shutdown(config::get().control.hum_interval) {
+            return Ok(());
+        }
         sensor_data = match get_sensor_data() {
             Ok(sensor_data) => sensor_data,
             Err(e) => {
@@ -81,63 +151,107 @@ pub fn entry_loop_hum() {
     }
 }
 
-pub fn entry_loop_temp() {
-    let project = match get_active_project() {
-        Ok(project) => project,
-        Err(e) => {
-            error!("Error: {}", e);
-            return;
-        }
+enum Purge {
+    Idle,
+    Running,
+    Done,
+}
+
+/* a purge starts `interval` after the previous one ended and lasts `duration` */
+fn fresh_air_purge(schedule: &FreshAirSchedule, last_purge: Instant) -> Purge {
+    if schedule.interval.is_zero() || schedule.duration.is_zero() {
+        return Purge::Idle;
+    }
+    let elapsed = last_purge.elapsed();
+    if elapsed < schedule.interval {
+        Purge::Idle
+    } else if elapsed < schedule.interval + schedule.duration {
+        Purge::Running
+    } else {
+        Purge::Done
+    }
+}
+
+/* split range: the heating PID holds the lower edge of the deadband, the cooling PID the upper
+one, and only the side of the target the temperature is on may drive its actuator */
+pub fn entry_loop_temp() -> WorkerResult {
+    let _zero_demand = ZeroDemandOnExit;
+    let project = match wait_for_active_project() {
+        Some(project) => project,
+        None => return Ok(()),
     };
-    let mut temp_pid: Pid<f32> = Pid::new(project.settings.temp, PID_LIMIT);
-    info!("temp_pid: {:?}", project.settings.temp);
-    temp_pid
-        .p(35.0, PID_LIMIT)
-        .i(0.09, PID_LIMIT)
-        .d(10.0, PID_LIMIT);
-
-    let mut sensor_data: SensorData = match get_sensor_data() {
-        Ok(sensor_data) => sensor_data,
-        Err(e) => {
-            error!("Error: {}", e);
-            return;
+    let control = &config::get().control;
+    let (heating_target, cooling_target) = split_targets(
+        project.settings.temp,
+        control.temp_deadband,
+        has_role(PinRole::Cooling),
+    );
+    let mut heating_pid = control_pid(heating_target, &control.heating_pid);
+    let mut cooling_pid = control_pid(cooling_target, &control.cooling_pid);
+    info!(
+        "temp_pid: {:?} heating below {} cooling above {}",
+        project.settings.temp, heating_target, cooling_target
+    );
+
+    let mut door_was_open = false;
+
+    let mut sensor_data: SensorData = get_sensor_data()?;
+    loop {
+        let door_open = door::is_open();
+        if door_open != door_was_open {
+            hold_integral(&mut heating_pid, &control.heating_pid, door_open);
+            hold_integral(&mut cooling_pid, &control.cooling_pid, door_open);
+            door_was_open = door_open;
         }
-    };
-    match turn_off_heating() {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Error: {}", e);
+        /* the idle side starts from zero instead of a wound up integral when it takes over */
+        let heating = sensor_data.temp < project.settings.temp;
+        if !door_open {
+            if heating {
+                cooling_pid.reset_integral_term();
+            } else {
+                heating_pid.reset_integral_term();
+            }
         }
-    };
-    loop {
-        let temp_on_percentage = temp_pid.next_control_output(sensor_data.temp).output / PID_LIMIT;
-        let temp_on_time = temp_on_percentage * TEMP_DUTY_CYCLE;
-        let temp_off_time = TEMP_DUTY_CYCLE - temp_on_time;
+        let heating_output = heating_pid.next_control_output(sensor_data.temp).output;
+        let cooling_output = -cooling_pid.next_control_output(sensor_data.temp).output;
+        let (heating_on_percentage, cooling_on_percentage) = if control::is_paused() {
+            heating_pid.reset_integral_term();
+            cooling_pid.reset_integral_term();
+            (0.0, 0.0)
+        } else if door_open {
+            (0.0, 0.0)
+        } else if heating {
+            (heating_output.max(0.0) / PID_LIMIT, 0.0)
+        } else {
+            (0.0, cooling_output.max(0.0) / PID_LIMIT)
+        };
         warn!(
-            "target_temp: {} current_temp: {} temp_on_time: {}",
-            project.settings.temp, sensor_data.temp, temp_on_time
+            "target_temp: {} current_temp: {} temp_on_percentage: {} cool_on_percentage: {}",
+            project.settings.temp, sensor_data.temp, heating_on_percentage, cooling_on_percentage
         );
-        task::spawn(async move {
-            if temp_on_percentage > 0.0 {
-                match turn_on_heating() {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Error: {}", e);
-                    }
-                }
-                task::sleep(Duration::from_secs_f32(temp_on_time)).await;
-                if temp_on_percentage < 0.995 {
-                    match turn_off_heating() {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error: {}", e);
-                        }
-                    };
-                }
-            }
-            task::sleep(Duration::from_secs_f32(temp_off_time)).await;
-        });
-        thread::sleep(std::time::Duration::from_secs_f32(TEMP_DUTY_CYCLE));
+        output::set_demand(Actuator::Heating, heating_on_percentage)?;
+        output::set_demand(Actuator::Cooling, cooling_on_percentage)?;
+        metrics::set(Gauge::TargetTemperature, project.settings.temp as f64);
+        metrics::set(Gauge::TempPidOutput, heating_output as f64);
+        metrics::set(Gauge::CoolingPidOutput, cooling_output as f64);
+        let time = chrono::Utc::now().timestamp() as u64;
+        influx::queue(influx::controller_line(
+            "temperature",
+            heating_target,
+            heating_output,
+            output::delivered_duty(Actuator::Heating)?,
+            time,
+        ));
+        influx::queue(influx::controller_line(
+            "cooling",
+            cooling_target,
+            cooling_output,
+            output::delivered_duty(Actuator::Cooling)?,
+            time,
+        ));
+        if !sleep_unless_shutdown(config::get().control.temp_interval) {
+            return Ok(());
+        }
         sensor_data = match get_sensor_data() {
             Ok(sensor_data) => sensor_data,
             Err(e) => {
@@ -147,3 +261,49 @@ pub fn entry_loop_temp() {
         };
     }
 }
+
+/* targets of the lower and upper actuator (heating and cooling, humidifier and fan); without an
+upper one the lower one holds the setpoint itself as it did before the deadband existed */
+fn split_targets(setpoint: f32, deadband: f32, split: bool) -> (f32, f32) {
+    if split {
+        (setpoint - deadband / 2.0, setpoint + deadband / 2.0)
+    } else {
+        (setpoint, setpoint)
+    }
+}
+
+/* the integral keeps its value while the door is open so the loop resumes where it was instead of
+winding up against the disturbance; the integral term is stored premultiplied by the gain, so a
+zero gain stops accumulating without clearing it */
+fn hold_integral(pid: &mut Pid<f32>, tuning: &PidTuning, hold: bool) {
+    pid.i(if hold { 0.0 } else { tuning.i }, PID_LIMIT);
+}
+
+fn control_pid(target: f32, tuning: &PidTuning) -> Pid<f32> {
+    let mut pid: Pid<f32> = Pid::new(target, PID_LIMIT);
+    pid.p(tuning.p, PID_LIMIT)
+        .i(tuning.i, PID_LIMIT)
+        .d(tuning.d, PID_LIMIT);
+    pid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadband_splits_the_setpoint() {
+        assert_eq!(split_targets(30.0, 1.0, true), (29.5, 30.5));
+    }
+
+    #[test]
+    fn without_cooling_heating_holds_the_setpoint() {
+        assert_eq!(split_targets(30.0, 1.0, false), (30.0, 30.0));
+    }
+
+    #[test]
+    fn without_fan_the_humidifier_holds_the_setpoint() {
+        assert_eq!(split_targets(80.0, 4.0, true), (78.0, 82.0));
+        assert_eq!(split_targets(80.0, 4.0, false), (80.0, 80.0));
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use super::supervisor::{sleep_unless_shutdown, WorkerResult};
+use crate::service::{
+    gpio::{
+        has_role, output_mode, set_output_duty, turn_off_cooling, turn_off_fan, turn_off_heating,
+        turn_off_humidifier, turn_on_cooling, turn_on_fan, turn_on_heating, turn_on_humidifier,
+        OutputMode, PinRole,
+    },
+    metrics::{self, Gauge},
+    output::{self, Actuator},
+};
+
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+type Switch = fn() -> Result<(), Box<dyn std::error::Error>>;
+
+pub fn entry_loop_heating() -> WorkerResult {
+    if !has_role(PinRole::Heating) {
+        info!("no heating pin configured");
+        return Ok(());
+    }
+    drive(
+        Actuator::Heating,
+        PinRole::Heating,
+        turn_on_heating,
+        turn_off_heating,
+        Gauge::HeatingDutyCycle,
+    )
+}
+
+pub fn entry_loop_cooling() -> WorkerResult {
+    if !has_role(PinRole::Cooling) {
+        info!("no cooling pin configured");
+        return Ok(());
+    }
+    drive(
+        Actuator::Cooling,
+        PinRole::Cooling,
+        turn_on_cooling,
+        turn_off_cooling,
+        Gauge::CoolingDutyCycle,
+    )
+}
+
+pub fn entry_loop_humidifier() -> WorkerResult {
+    if !has_role(PinRole::Humidifier) {
+        info!("no humidifier pin configured");
+        return Ok(());
+    }
+    drive(
+        Actuator::Humidifier,
+        PinRole::Humidifier,
+        turn_on_humidifier,
+        turn_off_humidifier,
+        Gauge::HumidifierDutyCycle,
+    )
+}
+
+pub fn entry_loop_fan() -> WorkerResult {
+    if !has_role(PinRole::Fan) {
+        info!("no fan pin configured");
+        return Ok(());
+    }
+    drive(
+        Actuator::Fan,
+        PinRole::Fan,
+        turn_on_fan,
+        turn_off_fan,
+        Gauge::FanDutyCycle,
+    )
+}
+
+/* the only place the output of an actuator is switched while the control loops run */
+fn drive(
+    actuator: Actuator,
+    role: PinRole,
+    turn_on: Switch,
+    turn_off: Switch,
+    gauge: Gauge,
+) -> WorkerResult {
+    if output_mode(role) == OutputMode::Pwm {
+        return drive_pwm(actuator, role, turn_off, gauge);
+    }
+    turn_off()?;
+    let mut on = false;
+    loop {
+        let want_on = output::update(actuator)?;
+        if want_on != on {
+            if want_on {
+                turn_on()?;
+            } else {
+                turn_off()?;
+            }
+            on = want_on;
+        }
+        metrics::set(gauge, output::delivered_duty(actuator)? as f64);
+        if !sleep_unless_shutdown(TICK_INTERVAL) {
+            output::force_off(actuator)?;
+            turn_off()?;
+            return Ok(());
+        }
+    }
+}
+
+/* the demand goes to the pin as duty cycle, no windows or minimum on and off times */
+fn drive_pwm(actuator: Actuator, role: PinRole, turn_off: Switch, gauge: Gauge) -> WorkerResult {
+    turn_off()?;
+    let mut duty = 0.0;
+    loop {
+        let demand = output::pass_through(actuator)?;
+        if demand != duty {
+            set_output_duty(role, demand)?;
+            duty = demand;
+        }
+        metrics::set(gauge, duty as f64);
+        if !sleep_unless_shutdown(TICK_INTERVAL) {
+            output::force_off(actuator)?;
+            turn_off()?;
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+use super::supervisor::{sleep_unless_shutdown, WorkerResult};
+use crate::service::retention::{downsample_sensor_data, thin_webcam_frames};
+
+const RETENTION_INTERVAL: u64 = 3600;
+
+pub fn entry_loop() -> WorkerResult {
+    loop {
+        let now = chrono::Utc::now().timestamp() as u64;
+        match downsample_sensor_data(now) {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error: {}", e);
+            }
+        }
+        match thin_webcam_frames() {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error: {}", e);
+            }
+        }
+        if !sleep_unless_shutdown(Duration::from_secs(RETENTION_INTERVAL)) {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use rocket::fairing::AdHoc;
+use std::time::Duration;
+
+use super::supervisor::{request_shutdown, wait_for_workers};
+use crate::service::{gpio::disable_outputs, influx};
+
+const WORKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/* rocket runs shutdown fairings on ctrl-c and SIGTERM as well as on a programmatic shutdown */
+pub fn fairing() -> AdHoc {
+    AdHoc::on_shutdown("Safe actuator shutdown", |_| {
+        Box::pin(async {
+            shutdown().await;
+        })
+    })
+}
+
+pub async fn shutdown() {
+    info!("shutting down, stopping control loops");
+    request_shutdown();
+    match disable_outputs() {
+        Ok(_) => info!("all actuators switched off"),
+        Err(e) => error!("Error: {}", e),
+    }
+    if !wait_for_workers(WORKER_TIMEOUT).await {
+        error!("Error: not all workers stopped in time");
+    }
+    match rocket::tokio::task::spawn_blocking(|| influx::flush().map_err(|e| e.to_string())).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => error!("Error: {}", e),
+        Err(e) => error!("Error: {}", e),
+    }
+}
@@ -0,0 +1,165 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+/* a worker that ran this long before failing starts again with the minimum backoff */
+const HEALTHY_RUNTIME: Duration = Duration::from_secs(600);
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub type WorkerResult = Result<(), Box<dyn std::error::Error>>;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkerState {
+    Running,
+    Restarting,
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WorkerStatus {
+    pub name: &'static str,
+    pub state: WorkerState,
+    pub started_at: u64,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<u64>,
+}
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref WORKERS: Mutex<BTreeMap<&'static str, WorkerStatus>> = Mutex::new(BTreeMap::new());
+}
+
+/* run the worker on its own thread and restart it with backoff whenever it fails or panics;
+a worker returning Ok is considered finished and is not restarted */
+pub fn spawn(name: &'static str, entry: fn() -> WorkerResult) {
+    update(name, |status| status.state = WorkerState::Running);
+    thread::spawn(move || {
+        let mut backoff = BACKOFF_MIN;
+        loop {
+            let started = Instant::now();
+            update(name, |status| {
+                status.state = WorkerState::Running;
+                status.started_at = now();
+            });
+            let result = thread::Builder::new()
+                .name(name.to_string())
+                .spawn(move || entry().map_err(|e| e.to_string()))
+                .map_err(|e| e.to_string())
+                .and_then(|handle| handle.join().map_err(panic_message).and_then(|r| r));
+            match result {
+                Ok(_) => {
+                    info!("worker {} finished", name);
+                    update(name, |status| status.state = WorkerState::Stopped);
+                    return;
+                }
+                Err(e) if is_shutting_down() => {
+                    error!("Error: worker {} failed during shutdown: {}", name, e);
+                    update(name, |status| status.state = WorkerState::Stopped);
+                    return;
+                }
+                Err(e) => {
+                    error!("Error: worker {} failed: {}", name, e);
+                    if started.elapsed() > HEALTHY_RUNTIME {
+                        backoff = BACKOFF_MIN;
+                    }
+                    update(name, |status| {
+                        status.state = WorkerState::Restarting;
+                        status.restarts += 1;
+                        status.last_error = Some(e.clone());
+                        status.last_error_at = Some(now());
+                    });
+                    if !sleep_unless_shutdown(backoff) {
+                        update(name, |status| status.state = WorkerState::Stopped);
+                        return;
+                    }
+                    backoff = (backoff * 2).min(BACKOFF_MAX);
+                }
+            }
+        }
+    });
+}
+
+/* workers are expected to return Ok once they see this */
+pub fn request_shutdown() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/* sleep in short slices, returns false as soon as shutdown is requested */
+pub fn sleep_unless_shutdown(duration: Duration) -> bool {
+    let started = Instant::now();
+    while !is_shutting_down() {
+        let remaining = duration.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return true;
+        }
+        thread::sleep(remaining.min(SHUTDOWN_POLL_INTERVAL));
+    }
+    false
+}
+
+pub async fn wait_for_workers(timeout: Duration) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        if get_worker_status()
+            .iter()
+            .all(|worker| worker.state == WorkerState::Stopped)
+        {
+            return true;
+        }
+        async_std::task::sleep(SHUTDOWN_POLL_INTERVAL).await;
+    }
+    false
+}
+
+pub fn get_worker_status() -> Vec<WorkerStatus> {
+    match WORKERS.lock() {
+        Ok(workers) => workers.values().cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn update(name: &'static str, f: impl FnOnce(&mut WorkerStatus)) {
+    if let Ok(mut workers) = WORKERS.lock() {
+        let status = workers.entry(name).or_insert(WorkerStatus {
+            name,
+            state: WorkerState::Running,
+            started_at: now(),
+            restarts: 0,
+            last_error: None,
+            last_error_at: None,
+        });
+        f(status);
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => format!("panicked: {}", message),
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => format!("panicked: {}", message),
+            None => String::from("panicked"),
+        },
+    }
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use super::supervisor::{sleep_unless_shutdown, WorkerResult};
+use crate::service::timelapse;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/* renders queued timelapse jobs one after another, rendering is too slow for a request */
+pub fn entry_loop() -> WorkerResult {
+    timelapse::clean_up()?;
+    loop {
+        match timelapse::start_next() {
+            Some(job) => timelapse::run(&job),
+            None => {
+                if !sleep_unless_shutdown(POLL_INTERVAL) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
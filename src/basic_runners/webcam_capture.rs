@@ -0,0 +1,209 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use super::supervisor::{sleep_unless_shutdown, WorkerResult};
+use crate::service::{
+    analysis, config,
+    database::project::get_active_project,
+    metrics::{self, Counter},
+    overlay::{self, OverlayData},
+    timelapse,
+    webcam::{
+        camera_format, get_capture_settings, has_snapshot_request, has_stream_viewers,
+        publish_stream_frame, store_frame, take_snapshot_requests, StorageFormat,
+    },
+};
+use image::RgbImage;
+use nokhwa::Camera;
+
+const RECONNECT_MIN: Duration = Duration::from_secs(5);
+const RECONNECT_MAX: Duration = Duration::from_secs(300);
+/* keep the stream open between captures up to this interval, otherwise reopen it per frame */
+const CONTINUOUS_STREAM_MAX: Duration = Duration::from_secs(10);
+const WARMUP_FRAMES: usize = 5;
+const SNAPSHOT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+pub fn entry_loop() -> WorkerResult {
+    let settings = get_capture_settings();
+    let continuous = settings.interval <= CONTINUOUS_STREAM_MAX;
+    let mut reconnect = RECONNECT_MIN;
+    loop {
+        let mut camera = match open_camera(continuous) {
+            Ok(camera) => camera,
+            Err(e) => {
+                error!("Error: {}", e);
+                analysis::track_project(get_active_project().ok().map(|project| project.id));
+                for request in take_snapshot_requests() {
+                    let _ = request.send(Err(e.to_string()));
+                }
+                if !sleep_unless_shutdown(reconnect) {
+                    return Ok(());
+                }
+                reconnect = (reconnect * 2).min(RECONNECT_MAX);
+                continue;
+            }
+        };
+        reconnect = RECONNECT_MIN;
+        let mut next_capture = Instant::now();
+        loop {
+            let snapshots = take_snapshot_requests();
+            let due = Instant::now() >= next_capture;
+            if due {
+                next_capture = Instant::now() + settings.interval;
+            }
+            let active = get_active_project().ok();
+            analysis::track_project(active.as_ref().map(|project| project.id));
+            if active.is_none() {
+                for request in snapshots {
+                    let _ = request.send(Err(String::from("No active project")));
+                }
+            } else if due || !snapshots.is_empty() {
+                let result = take_webcam_image(&mut camera, continuous);
+                for request in snapshots {
+                    let _ = request.send(match &result {
+                        Ok(path) => Ok(path.clone()),
+                        Err(e) => Err(e.to_string()),
+                    });
+                }
+                if let Err(e) = result {
+                    error!("Error: {}", e);
+                    break;
+                }
+            }
+            match wait_for_capture(&mut camera, continuous, next_capture) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/* sleep until the next regular capture unless a snapshot is requested, false on shutdown.
+Meanwhile frames go to live stream viewers; the stream is only kept open for them while
+anybody is watching */
+fn wait_for_capture(
+    camera: &mut Camera,
+    continuous: bool,
+    next_capture: Instant,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let stream_interval = Duration::from_secs(1) / get_capture_settings().stream.fps;
+    let mut next_stream_frame = Instant::now();
+    loop {
+        let remaining = next_capture.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || has_snapshot_request() {
+            return Ok(true);
+        }
+        let mut wait = remaining.min(SNAPSHOT_POLL_INTERVAL);
+        if has_stream_viewers() {
+            if !camera.is_stream_open() {
+                camera.open_stream()?;
+            }
+            if Instant::now() >= next_stream_frame {
+                next_stream_frame = Instant::now() + stream_interval;
+                publish_stream_frame(&camera.frame()?)?;
+            }
+            wait = wait.min(next_stream_frame.saturating_duration_since(Instant::now()));
+        } else if !continuous && camera.is_stream_open() {
+            camera.stop_stream()?;
+        }
+        if !sleep_unless_shutdown(wait) {
+            return Ok(false);
+        }
+    }
+}
+
+fn open_camera(continuous: bool) -> Result<Camera, Box<dyn std::error::Error>> {
+    let mut camera = Camera::new(0, Some(camera_format(get_capture_settings())))?;
+    if continuous {
+        camera.open_stream()?;
+    }
+    Ok(camera)
+}
+
+fn take_webcam_image(
+    camera: &mut Camera,
+    continuous: bool,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    /* the stream may also be open for live viewers */
+    let result = if continuous || camera.is_stream_open() {
+        save_webcam_image(camera)
+    } else {
+        save_webcam_image_from_new_stream(camera)
+    };
+    match result {
+        Ok(_) => {
+            metrics::inc(Counter::WebcamFrames);
+            metrics::observe_webcam_latency(started.elapsed());
+        }
+        Err(_) => metrics::inc(Counter::WebcamFrameErrors),
+    }
+    result
+}
+
+/* a stream left open for minutes hands out stale buffers, so open it just for this frame */
+fn save_webcam_image_from_new_stream(
+    camera: &mut Camera,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    camera.open_stream()?;
+    for _ in 0..WARMUP_FRAMES {
+        camera.frame()?;
+    }
+    let result = save_webcam_image(camera);
+    camera.stop_stream()?;
+    result
+}
+
+fn save_webcam_image(camera: &mut Camera) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let (mut frame, mjpeg) = match capture_frame(camera) {
+        Ok(frame) => frame,
+        Err(e) => {
+            error!("Error: {}", e);
+            return Err(e);
+        }
+    };
+    let project = get_active_project()?;
+    let time = chrono::Utc::now().timestamp() as u64;
+    /* the overlay would count as white pixels */
+    if let Err(e) = analysis::frame_captured(project.id, &frame, time) {
+        error!("Error: {}", e);
+    }
+    let overlay_settings = &config::get().overlay;
+    if overlay_settings.frames {
+        overlay::draw(
+            &mut frame,
+            &OverlayData::live(&project),
+            overlay_settings.scale,
+        );
+    }
+    let project = project.id;
+    match store_frame(project, time, &frame, mjpeg.as_deref()) {
+        Ok(path) => {
+            timelapse::frame_added(project, time);
+            Ok(path)
+        }
+        Err(e) => {
+            error!("Error: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/* the decoded frame and, when frames are stored as MJPEG, the camera's own JPEG */
+type CapturedFrame = (RgbImage, Option<Vec<u8>>);
+
+/* analysis and overlay need the decoded frame even when the JPEG is stored as is */
+fn capture_frame(camera: &mut Camera) -> Result<CapturedFrame, Box<dyn std::error::Error>> {
+    if get_capture_settings().storage.format != StorageFormat::Mjpeg {
+        return Ok((camera.frame()?, None));
+    }
+    let mjpeg = camera.frame_raw()?.into_owned();
+    let frame = image::load_from_memory_with_format(&mjpeg, image::ImageFormat::Jpeg)?.into_rgb();
+    Ok((frame, Some(mjpeg)))
+}
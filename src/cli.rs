@@ -0,0 +1,49 @@
+use std::fs::File;
+
+use crate::service::backup::{create_backup, restore_backup};
+
+const USAGE: &str = "usage:
+    fermentation-pi backup <archive.tar.zst> [--no-webcam]
+    fermentation-pi restore <archive.tar.zst> [--dry-run]
+stop the service before restoring, it writes the files a restore replaces";
+
+/* run a subcommand and return the process exit code */
+pub fn run(args: &[String]) -> i32 {
+    let result = match (args.first().map(String::as_str), args.get(1)) {
+        (Some("backup"), Some(path)) => backup(path, !args.iter().any(|a| a == "--no-webcam")),
+        (Some("restore"), Some(path)) => restore(path, args.iter().any(|a| a == "--dry-run")),
+        _ => {
+            eprintln!("{USAGE}");
+            return 2;
+        }
+    };
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn backup(path: &str, include_webcam: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = create_backup(File::create(path)?, include_webcam)?;
+    println!("wrote {} files to {}", manifest.files.len(), path);
+    Ok(())
+}
+
+fn restore(path: &str, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let report = restore_backup(File::open(path)?, dry_run)?;
+    for error in report.errors.iter() {
+        eprintln!("{}", error);
+    }
+    if !report.errors.is_empty() {
+        return Err(Box::from("Backup validation failed, nothing restored"));
+    }
+    if dry_run {
+        println!("{} files ({} bytes) are valid", report.files, report.bytes);
+    } else {
+        println!("restored {} files ({} bytes)", report.files, report.bytes);
+    }
+    Ok(())
+}
@@ -1,43 +1,155 @@
+use basic_runners::supervisor;
+use rocket::{figment::Figment, Build, Rocket};
 use rocket_cors::{AllowedOrigins, CorsOptions};
-use std::thread;
+use std::{env, process};
 
 #[macro_use]
 extern crate rocket;
 extern crate engiffen;
 extern crate rocket_cors;
+mod cli;
 mod route {
+    pub mod admin;
+    pub mod alarm;
+    pub mod config;
+    pub mod control;
     pub mod heartbeat;
     pub mod index;
+    pub mod metrics;
     pub mod project;
     pub mod sensor_value;
     pub mod webcam;
 }
 
 pub mod service {
+    pub mod alarm;
+    pub mod analysis;
+    pub mod backup;
+    pub mod button;
+    pub mod config;
+    pub mod control;
     pub mod database;
+    pub mod door;
+    pub mod export;
     pub mod gpio;
+    pub mod indicator;
+    pub mod influx;
+    pub mod metrics;
+    pub mod output;
+    pub mod overlay;
+    pub mod retention;
     pub mod sensor;
+    pub mod timelapse;
     pub mod webcam;
 }
 
 pub mod basic_runners {
+    pub mod buttons;
+    pub mod door;
+    pub mod indicator;
+    pub mod influx_pusher;
     pub mod manage_climate;
+    pub mod output_driver;
+    pub mod retention;
     pub mod sensor_logger;
+    pub mod shutdown;
+    pub mod supervisor;
+    pub mod timelapse_renderer;
+    pub mod webcam_capture;
 }
 
-#[launch]
-async fn rocket() -> _ {
-    thread::spawn(|| basic_runners::sensor_logger::entry_loop());
-    thread::spawn(|| basic_runners::manage_climate::entry_loop_hum());
-    thread::spawn(|| basic_runners::manage_climate::entry_loop_temp());
+#[rocket::main]
+async fn main() {
+    let figment = service::config::figment();
+    match service::config::load(&figment) {
+        Ok(config) => service::config::init(config),
+        Err(e) => {
+            eprintln!("Error: invalid config: {}", e);
+            process::exit(1);
+        }
+    }
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        process::exit(cli::run(&args));
+    }
+    if let Err(e) = rocket(figment).launch().await {
+        error!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn rocket(figment: Figment) -> Rocket<Build> {
+    supervisor::spawn("sensor_logger", basic_runners::sensor_logger::entry_loop);
+    supervisor::spawn("webcam_capture", basic_runners::webcam_capture::entry_loop);
+    supervisor::spawn(
+        "climate_humidity",
+        basic_runners::manage_climate::entry_loop_hum,
+    );
+    supervisor::spawn(
+        "climate_temperature",
+        basic_runners::manage_climate::entry_loop_temp,
+    );
+    supervisor::spawn(
+        "output_heating",
+        basic_runners::output_driver::entry_loop_heating,
+    );
+    supervisor::spawn(
+        "output_cooling",
+        basic_runners::output_driver::entry_loop_cooling,
+    );
+    supervisor::spawn(
+        "output_humidifier",
+        basic_runners::output_driver::entry_loop_humidifier,
+    );
+    supervisor::spawn("output_fan", basic_runners::output_driver::entry_loop_fan);
+    supervisor::spawn("indicator", basic_runners::indicator::entry_loop);
+    supervisor::spawn("buttons", basic_runners::buttons::entry_loop);
+    supervisor::spawn("door", basic_runners::door::entry_loop);
+    supervisor::spawn("influx_pusher", basic_runners::influx_pusher::entry_loop);
+    supervisor::spawn("retention", basic_runners::retention::entry_loop);
+    supervisor::spawn(
+        "timelapse_renderer",
+        basic_runners::timelapse_renderer::entry_loop,
+    );
     let mut index_routes = routes![route::index::index, route::index::files];
     index_routes[1].rank = 2;
     let cors = CorsOptions::default().allowed_origins(AllowedOrigins::all());
 
-    rocket::build()
+    rocket::custom(figment)
         .mount("/", index_routes)
         .mount("/heartbeat", routes![route::heartbeat::get])
-        .mount("/webcam", routes![route::webcam::gif])
+        .mount(
+            "/admin",
+            routes![route::admin::backup, route::admin::restore],
+        )
+        .mount(
+            "/alarm",
+            routes![route::alarm::get, route::alarm::acknowledge],
+        )
+        .mount("/config", routes![route::config::get])
+        .mount(
+            "/control",
+            routes![
+                route::control::get,
+                route::control::pause,
+                route::control::resume
+            ],
+        )
+        .mount("/metrics", routes![route::metrics::get])
+        .mount(
+            "/webcam",
+            routes![
+                route::webcam::gif,
+                route::webcam::latest,
+                route::webcam::stream,
+                route::webcam::frames,
+                route::webcam::frame,
+                route::webcam::snapshot,
+                route::webcam::timelapse,
+                route::webcam::timelapse_job,
+                route::webcam::timelapse_output
+            ],
+        )
         .mount(
             "/project",
             routes![
@@ -48,12 +160,23 @@ async fn rocket() -> _ {
                 route::project::delete,
                 route::project::start,
                 route::project::end,
-                route::project::set_settings
+                route::project::set_settings,
+                route::project::event,
+                route::project::export,
+                route::project::all_usage,
+                route::project::usage,
+                route::project::analysis
             ],
         )
         .mount(
             "/sensor",
-            routes![route::sensor_value::get, route::sensor_value::get_historic],
+            routes![
+                route::sensor_value::get,
+                route::sensor_value::get_historic,
+                route::sensor_value::export_influx
+            ],
         )
         .attach(cors.to_cors().unwrap())
+        .attach(service::metrics::HttpMetrics)
+        .attach(basic_runners::shutdown::fairing())
 }
@@ -0,0 +1,142 @@
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::Json;
+use rocket::tokio::{fs::File, task::spawn_blocking};
+use sha2::{Digest, Sha256};
+
+use super::project::ExportFile;
+use crate::service::{
+    backup::{create_backup, restore_backup, RestoreReport},
+    config,
+};
+
+/* `Authorization: Bearer <admin.token>`, the admin routes are forbidden without a configured
+token. CORS allows every origin, so the token is all that keeps other pages off these routes */
+pub struct AdminToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let expected = match config::get().admin.token.as_deref() {
+            Some(token) => token,
+            None => return request::Outcome::Error((Status::Forbidden, ())),
+        };
+        let given = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(given) if tokens_match(given, expected) => request::Outcome::Success(AdminToken),
+            _ => request::Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/* compares digests so the time taken does not tell how much of the token was right */
+fn tokens_match(given: &str, expected: &str) -> bool {
+    Sha256::digest(given.as_bytes())
+        .iter()
+        .zip(Sha256::digest(expected.as_bytes()).iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+#[get("/backup?<webcam>")]
+pub async fn backup(_admin: AdminToken, webcam: Option<bool>) -> Option<ExportFile<File>> {
+    let created_at = chrono::Utc::now().timestamp();
+    let path = std::env::temp_dir().join(format!("fermentation-backup-{}.tar.zst", created_at));
+    let backup_path = path.clone();
+    let result = spawn_blocking(move || {
+        let file = std::fs::File::create(&backup_path).map_err(|e| e.to_string())?;
+        create_backup(file, webcam.unwrap_or(true)).map_err(|e| e.to_string())
+    })
+    .await;
+    match result {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => {
+            error!("Error: {}", e);
+            return None;
+        }
+        Err(e) => {
+            error!("Error: {}", e);
+            return None;
+        }
+    }
+    let file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Error: {}", e);
+            return None;
+        }
+    };
+    let _ = std::fs::remove_file(&path);
+    Some(ExportFile {
+        inner: file,
+        content_type: ContentType::new("application", "zstd"),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"fermentation-backup-{}.tar.zst\"",
+                created_at
+            ),
+        ),
+    })
+}
+
+/* only verifies an uploaded backup: the workers keep writing the files a restore replaces, so
+restoring is done with `fermentation-pi restore` while the service is stopped */
+#[post("/restore", data = "<archive>")]
+pub async fn restore(_admin: AdminToken, archive: Data<'_>) -> Json<Option<RestoreReport>> {
+    let path = std::env::temp_dir().join(format!(
+        "fermentation-restore-{}.tar.zst",
+        chrono::Utc::now().timestamp()
+    ));
+    let limit = config::get().admin.max_upload_mb.mebibytes();
+    match archive.open(limit).into_file(&path).await {
+        Ok(file) if file.is_complete() => {}
+        Ok(_) => {
+            error!("Error: backup archive too large");
+            let _ = std::fs::remove_file(&path);
+            return Json(None);
+        }
+        Err(e) => {
+            error!("Error: {}", e);
+            let _ = std::fs::remove_file(&path);
+            return Json(None);
+        }
+    }
+    let restore_path = path.clone();
+    let result = spawn_blocking(move || {
+        let file = std::fs::File::open(&restore_path).map_err(|e| e.to_string())?;
+        restore_backup(file, true).map_err(|e| e.to_string())
+    })
+    .await;
+    let _ = std::fs::remove_file(&path);
+    Json(match result {
+        Ok(Ok(report)) => Some(report),
+        Ok(Err(e)) => {
+            error!("Error: {}", e);
+            None
+        }
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_exact_token_matches() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secre", "secret"));
+        assert!(!tokens_match("secret ", "secret"));
+        assert!(!tokens_match("", "secret"));
+    }
+}
@@ -0,0 +1,19 @@
+use rocket::serde::json::Json;
+
+use crate::service::alarm::{acknowledge_all, active_alarms, Alarm};
+
+#[get("/")]
+pub fn get() -> Json<Vec<Alarm>> {
+    Json(active_alarms())
+}
+
+#[post("/acknowledge")]
+pub fn acknowledge() -> Json<Option<usize>> {
+    Json(match acknowledge_all() {
+        Ok(acknowledged) => Some(acknowledged),
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    })
+}
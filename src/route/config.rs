@@ -0,0 +1,8 @@
+use rocket::serde::json::Json;
+
+use crate::service::config::{self, Config};
+
+#[get("/")]
+pub fn get() -> Json<Config> {
+    Json(config::get().redacted())
+}
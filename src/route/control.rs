@@ -0,0 +1,30 @@
+use rocket::serde::json::Json;
+
+use crate::service::control::{self, ControlStatus};
+
+#[get("/")]
+pub fn get() -> Json<ControlStatus> {
+    Json(control::status())
+}
+
+#[post("/pause")]
+pub fn pause() -> Json<Option<ControlStatus>> {
+    Json(match control::pause() {
+        Ok(status) => Some(status),
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    })
+}
+
+#[post("/resume")]
+pub fn resume() -> Json<Option<ControlStatus>> {
+    Json(match control::resume() {
+        Ok(status) => Some(status),
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    })
+}
@@ -1,4 +1,21 @@
+use rocket::serde::json::Json;
+use serde::Serialize;
+
+use crate::basic_runners::supervisor::{get_worker_status, WorkerState, WorkerStatus};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    healthy: bool,
+    workers: Vec<WorkerStatus>,
+}
+
 #[get("/")]
-pub fn get() -> &'static str {
-    "Hello, world!"
+pub fn get() -> Json<HealthReport> {
+    let workers = get_worker_status();
+    Json(HealthReport {
+        healthy: workers
+            .iter()
+            .all(|worker| worker.state != WorkerState::Restarting),
+        workers,
+    })
 }
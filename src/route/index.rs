@@ -1,15 +1,17 @@
 use rocket::fs::NamedFile;
 use std::io;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+
+use crate::service::config;
 
 #[get("/")]
 pub async fn index() -> io::Result<NamedFile> {
-    NamedFile::open("fermentation-pi-client/dist/index.html").await
+    NamedFile::open(config::get().paths.client.join("index.html")).await
 }
 
 #[get("/<file..>")]
 pub async fn files(file: PathBuf) -> Option<NamedFile> {
-    NamedFile::open(Path::new("fermentation-pi-client/dist/").join(file))
+    NamedFile::open(config::get().paths.client.join(file))
         .await
         .ok()
 }
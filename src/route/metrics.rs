@@ -0,0 +1,14 @@
+use rocket::response::content::RawText;
+
+use crate::service::metrics::render;
+
+#[get("/")]
+pub fn get() -> Option<RawText<String>> {
+    match render() {
+        Ok(metrics) => Some(RawText(metrics)),
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    }
+}
@@ -7,19 +7,43 @@ pub struct CreateRequest {
     description: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct EventRequest {
+    kind: EventKind,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct PutRequest {
     name: Option<String>,
     description: Option<String>,
+    keep: Option<bool>,
 }
 
+use crate::service::analysis::{read_analysis, FrameAnalysis};
 use crate::service::database::project::{
-    create_new_project, delete_project, end_project, read_project, read_projects,
-    set_project_settings, start_project, update_project, Project, Settings,
+    add_event, create_new_project, delete_project, end_project, read_project, read_projects,
+    set_project_settings, start_project, update_project, EventKind, Project, Settings,
+};
+use crate::service::database::sensor::HistoricSensorData;
+use crate::service::export::{
+    csv_head, csv_rows, day_readings, json_head, json_readings, json_tail, project_days,
+    write_parquet, ExportFormat,
 };
+use crate::service::retention::{disk_usage, DiskUsage};
 use ::serde::Deserialize;
+use rocket::http::{ContentType, Header};
+use rocket::response::stream::ByteStream;
 use rocket::serde::json::Json;
+use rocket::tokio::{fs::File, io::AsyncReadExt, task::spawn_blocking};
+
+#[derive(Responder)]
+pub struct ExportFile<T> {
+    pub inner: T,
+    pub content_type: ContentType,
+    pub disposition: Header<'static>,
+}
 
 #[get("/")]
 pub fn all_projects() -> Json<Option<Vec<Project>>> {
@@ -37,6 +61,49 @@ pub fn get_project(id: u32) -> Json<Option<Project>> {
     })
 }
 
+#[get("/usage")]
+pub fn all_usage() -> Json<Option<Vec<DiskUsage>>> {
+    let projects = match read_projects() {
+        Ok(projects) => projects,
+        Err(e) => {
+            error!("Error: {}", e);
+            return Json(None);
+        }
+    };
+    Json(
+        projects
+            .iter()
+            .map(|project| disk_usage(project).ok())
+            .collect(),
+    )
+}
+
+#[get("/<id>/usage")]
+pub fn usage(id: u32) -> Json<Option<DiskUsage>> {
+    Json(match read_project(id) {
+        Ok(project) => disk_usage(&project).ok(),
+        Err(_) => None,
+    })
+}
+
+/* analysed frames of the project, optionally limited to start..=end */
+#[get("/<id>/analysis?<start>&<end>")]
+pub fn analysis(id: u32, start: Option<u64>, end: Option<u64>) -> Json<Option<Vec<FrameAnalysis>>> {
+    Json(match read_analysis(id) {
+        Ok(series) => Some(
+            series
+                .into_iter()
+                .filter(|analysis| start.is_none_or(|start| analysis.time >= start))
+                .filter(|analysis| end.is_none_or(|end| analysis.time <= end))
+                .collect(),
+        ),
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    })
+}
+
 #[post("/", format = "json", data = "<project>")]
 pub fn create(project: Json<CreateRequest>) {
     let _ = create_new_project(project.name.clone(), project.description.clone());
@@ -44,7 +111,12 @@ pub fn create(project: Json<CreateRequest>) {
 
 #[put("/<id>", format = "json", data = "<project>")]
 pub fn update(id: u32, project: Json<PutRequest>) -> Json<Option<Project>> {
-    let _ = update_project(id, project.name.clone(), project.description.clone());
+    let _ = update_project(
+        id,
+        project.name.clone(),
+        project.description.clone(),
+        project.keep,
+    );
     Json(match read_project(id) {
         Ok(project) => Some(project),
         Err(_) => None,
@@ -76,3 +148,153 @@ pub fn end(id: u32) {
 pub fn set_settings(id: u32, settings: Json<Settings>) {
     let _ = set_project_settings(id, settings.0);
 }
+
+#[post("/<id>/event", format = "json", data = "<event>")]
+pub fn event(id: u32, event: Json<EventRequest>) -> Json<Option<Project>> {
+    if let Err(e) = add_event(id, event.kind) {
+        error!("Error: {}", e);
+        return Json(None);
+    }
+    Json(read_project(id).ok())
+}
+
+/* day files are read on the blocking pool, not on the async executor */
+async fn blocking_day_readings(
+    project: &Project,
+    day: u64,
+) -> Result<Vec<HistoricSensorData>, String> {
+    let project = project.clone();
+    match spawn_blocking(move || day_readings(&project, day).map_err(|e| e.to_string())).await {
+        Ok(result) => result,
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[get("/<id>/export?<format>")]
+pub fn export(id: u32, format: Option<ExportFormat>) -> Option<ExportFile<ByteStream![Vec<u8>]>> {
+    let project = match read_project(id) {
+        Ok(project) => project,
+        Err(e) => {
+            error!("Error: {}", e);
+            return None;
+        }
+    };
+    let format = format.unwrap_or(ExportFormat::Json);
+    let content_type = match format {
+        ExportFormat::Csv => ContentType::CSV,
+        ExportFormat::Parquet => ContentType::Binary,
+        ExportFormat::Json => ContentType::JSON,
+    };
+    let disposition = Header::new(
+        "Content-Disposition",
+        format!(
+            "attachment; filename=\"project-{}.{}\"",
+            id,
+            format.extension()
+        ),
+    );
+
+    let stream = ByteStream! {
+        match format {
+            ExportFormat::Json => {
+                let head = match json_head(&project) {
+                    Ok(head) => head,
+                    Err(e) => {
+                        error!("Error: {}", e);
+                        return;
+                    }
+                };
+                yield head.into_bytes();
+                let mut first = true;
+                for day in project_days(&project) {
+                    let readings = match blocking_day_readings(&project, day).await {
+                        Ok(readings) => readings,
+                        Err(e) => {
+                            error!("Error: {}", e);
+                            return;
+                        }
+                    };
+                    let chunk = match json_readings(&readings, first) {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            error!("Error: {}", e);
+                            return;
+                        }
+                    };
+                    yield chunk.into_bytes();
+                    first = first && readings.is_empty();
+                }
+                yield json_tail().into_bytes();
+            }
+            ExportFormat::Csv => {
+                let head = match csv_head(&project) {
+                    Ok(head) => head,
+                    Err(e) => {
+                        error!("Error: {}", e);
+                        return;
+                    }
+                };
+                yield head.into_bytes();
+                for day in project_days(&project) {
+                    let readings = match blocking_day_readings(&project, day).await {
+                        Ok(readings) => readings,
+                        Err(e) => {
+                            error!("Error: {}", e);
+                            return;
+                        }
+                    };
+                    yield csv_rows(&project, &readings).into_bytes();
+                }
+            }
+            ExportFormat::Parquet => {
+                let path = std::env::temp_dir().join(format!(
+                    "fermentation-export-{}-{}.parquet",
+                    id,
+                    chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+                ));
+                let parquet_path = path.clone();
+                match spawn_blocking(move || {
+                    write_parquet(&project, &parquet_path).map_err(|e| e.to_string())
+                })
+                .await
+                {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => {
+                        error!("Error: {}", e);
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Error: {}", e);
+                        return;
+                    }
+                }
+                let mut file = match File::open(&path).await {
+                    Ok(file) => file,
+                    Err(e) => {
+                        error!("Error: {}", e);
+                        return;
+                    }
+                };
+                let _ = std::fs::remove_file(&path);
+                let mut buffer = vec![0; 64 * 1024];
+                loop {
+                    let read = match file.read(&mut buffer).await {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(e) => {
+                            error!("Error: {}", e);
+                            return;
+                        }
+                    };
+                    yield buffer[..read].to_vec();
+                }
+            }
+        }
+    };
+
+    Some(ExportFile {
+        inner: stream,
+        content_type,
+        disposition,
+    })
+}
@@ -1,7 +1,9 @@
 use chrono::Datelike;
+use rocket::response::content::RawText;
 use rocket::serde::json::Json;
 
 use crate::service::database::sensor::{get_all_data, HistoricSensorData, SensorData};
+use crate::service::influx::sensor_line;
 use crate::service::sensor::get_sensor_data;
 
 #[get("/historic/<start_ticks>/<end_ticks>")]
@@ -12,6 +14,22 @@ pub fn get_historic(start_ticks: u64, end_ticks: u64) -> Json<Option<Vec<Histori
     }
 }
 
+#[get("/export/influx/<start_ticks>/<end_ticks>")]
+pub fn export_influx(start_ticks: u64, end_ticks: u64) -> Option<RawText<String>> {
+    match sensor_database(start_ticks, end_ticks) {
+        Ok(db) => Some(RawText(
+            db.iter()
+                .map(sensor_line)
+                .collect::<Vec<String>>()
+                .join("\n"),
+        )),
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    }
+}
+
 #[get("/")]
 pub async fn get() -> Json<Option<SensorData>> {
     match get_sensor_data() {
@@ -1,11 +1,400 @@
 use rocket::fs::NamedFile;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, stream::ByteStream, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    select,
+    sync::broadcast::error::RecvError,
+    task::spawn_blocking,
+    time::sleep,
+};
+use rocket::Shutdown;
+use std::{
+    io::{self, Cursor, SeekFrom},
+    path::Path,
+    time::{Duration, Instant},
+};
 
-use crate::service::webcam::generate_gif;
+use crate::service::{
+    database::project::get_active_project,
+    timelapse::{enqueue, get_job, job_output, JobState, TimelapseJob, TimelapseRequest},
+    webcam::{
+        frame_page, frame_path, frame_time, latest_frame, request_snapshot, subscribe_stream,
+        FramePage,
+    },
+};
 
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(30);
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/* the renderer may be down or backing off, the request gives up instead of hanging */
+const JOB_WAIT_MAX: Duration = Duration::from_secs(600);
+const STREAM_BOUNDARY: &str = "frame";
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+/* longer ranges are cut short, browsers ask for the rest in further requests */
+const MAX_RANGE_LENGTH: u64 = 4 * 1024 * 1024;
+
+#[derive(Responder)]
+pub struct LiveStream<T> {
+    pub inner: T,
+    pub content_type: ContentType,
+    pub cache_control: Header<'static>,
+}
+
+#[derive(Responder)]
+pub struct FrameFile {
+    pub inner: NamedFile,
+    pub timestamp: Header<'static>,
+    pub cache_control: Header<'static>,
+}
+
+impl FrameFile {
+    async fn open(path: &Path, cache_control: &'static str) -> Option<FrameFile> {
+        let time = frame_time(path)?;
+        match NamedFile::open(path).await {
+            Ok(file) => Some(FrameFile {
+                inner: file,
+                timestamp: Header::new("X-Frame-Timestamp", time.to_string()),
+                cache_control: Header::new("Cache-Control", cache_control),
+            }),
+            Err(e) => {
+                error!("Error: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/* the Range header, browsers use it to seek in videos */
+pub struct ByteRange(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ByteRange {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(ByteRange(
+            request.headers().get_one("Range").map(String::from),
+        ))
+    }
+}
+
+impl ByteRange {
+    /* first and last byte of a single range, None serves the whole file and is used for missing
+    or multiple ranges, Err if the range lies outside the file */
+    fn resolve(&self, size: u64) -> Result<Option<(u64, u64)>, u64> {
+        let spec = match self
+            .0
+            .as_deref()
+            .and_then(|range| range.strip_prefix("bytes="))
+        {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return Ok(None),
+        };
+        let (start, end) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return Ok(None),
+        };
+        if size == 0 {
+            return Err(size);
+        }
+        let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) => (start, end.min(size - 1)),
+            (Ok(start), Err(_)) if end.is_empty() => (start, size - 1),
+            (Err(_), Ok(suffix)) if start.is_empty() => (size.saturating_sub(suffix), size - 1),
+            _ => return Ok(None),
+        };
+        if start > end || start >= size {
+            return Err(size);
+        }
+        Ok(Some((start, end.min(start + MAX_RANGE_LENGTH - 1))))
+    }
+}
+
+pub enum RangedFile {
+    Full {
+        file: File,
+        size: u64,
+        content_type: ContentType,
+    },
+    Partial {
+        bytes: Vec<u8>,
+        start: u64,
+        size: u64,
+        content_type: ContentType,
+    },
+    Unsatisfiable {
+        size: u64,
+    },
+}
+
+impl RangedFile {
+    pub async fn open(path: &Path, range: &ByteRange) -> io::Result<RangedFile> {
+        let mut file = File::open(path).await?;
+        let size = file.metadata().await?.len();
+        let content_type = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(ContentType::from_extension)
+            .unwrap_or(ContentType::Binary);
+        match range.resolve(size) {
+            Ok(None) => Ok(RangedFile::Full {
+                file,
+                size,
+                content_type,
+            }),
+            Ok(Some((start, end))) => {
+                file.seek(SeekFrom::Start(start)).await?;
+                let mut bytes = vec![0; (end - start + 1) as usize];
+                file.read_exact(&mut bytes).await?;
+                Ok(RangedFile::Partial {
+                    bytes,
+                    start,
+                    size,
+                    content_type,
+                })
+            }
+            Err(size) => Ok(RangedFile::Unsatisfiable { size }),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for RangedFile {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.raw_header("Accept-Ranges", "bytes");
+        match self {
+            RangedFile::Full {
+                file,
+                size,
+                content_type,
+            } => {
+                response
+                    .header(content_type)
+                    .sized_body(size as usize, file);
+            }
+            RangedFile::Partial {
+                bytes,
+                start,
+                size,
+                content_type,
+            } => {
+                let end = start + bytes.len() as u64 - 1;
+                response
+                    .status(Status::PartialContent)
+                    .header(content_type)
+                    .raw_header("Content-Range", format!("bytes {}-{}/{}", start, end, size))
+                    .sized_body(bytes.len(), Cursor::new(bytes));
+            }
+            RangedFile::Unsatisfiable { size } => {
+                response
+                    .status(Status::RangeNotSatisfiable)
+                    .raw_header("Content-Range", format!("bytes */{}", size));
+            }
+        }
+        response.ok()
+    }
+}
+
+/* the default timelapse, kept for older clients: waits for the render job to finish */
 #[get("/gif/<project>")]
 pub async fn gif(project: u32) -> Option<NamedFile> {
-    match generate_gif(project).await {
+    let job = match TimelapseRequest::default()
+        .resolve()
+        .and_then(|params| enqueue(project, params))
+    {
+        Ok(job) => job,
+        Err(e) => {
+            error!("Error: {}", e);
+            return None;
+        }
+    };
+    let started = Instant::now();
+    loop {
+        match get_job(job.id)?.state {
+            JobState::Done => break,
+            JobState::Failed => return None,
+            _ if started.elapsed() >= JOB_WAIT_MAX => {
+                error!("Error: timelapse job {} not done in time", job.id);
+                return None;
+            }
+            _ => sleep(JOB_POLL_INTERVAL).await,
+        }
+    }
+    NamedFile::open(job_output(job.id)?).await.ok()
+}
+
+/* queues a render, or returns the job already queued, running or done for the same request */
+#[post("/<project>/timelapse", format = "json", data = "<request>")]
+pub fn timelapse(project: u32, request: Json<TimelapseRequest>) -> Json<Option<TimelapseJob>> {
+    match request
+        .resolve()
+        .and_then(|params| enqueue(project, params))
+    {
+        Ok(job) => Json(Some(job)),
+        Err(e) => {
+            error!("Error: {}", e);
+            Json(None)
+        }
+    }
+}
+
+#[get("/<project>/timelapse/<job>")]
+pub fn timelapse_job(project: u32, job: u64) -> Json<Option<TimelapseJob>> {
+    Json(get_job(job).filter(|job| job.project == project))
+}
+
+#[get("/<project>/timelapse/<job>/output")]
+pub async fn timelapse_output(project: u32, job: u64, range: ByteRange) -> Option<RangedFile> {
+    get_job(job).filter(|job| job.project == project)?;
+    match RangedFile::open(&job_output(job)?, &range).await {
         Ok(file) => Some(file),
-        Err(_) => None,
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    }
+}
+
+/* most recent frame of the active project */
+#[get("/latest")]
+pub async fn latest() -> Option<FrameFile> {
+    let project = get_active_project().ok()?;
+    let path = match latest_frame(project.id) {
+        Ok(path) => path?,
+        Err(e) => {
+            error!("Error: {}", e);
+            return None;
+        }
+    };
+    FrameFile::open(&path, "no-store").await
+}
+
+/* MJPEG for <img> tags, the capture worker only streams while somebody is connected */
+#[get("/stream")]
+pub fn stream(mut shutdown: Shutdown) -> LiveStream<ByteStream![Vec<u8>]> {
+    let mut receiver = subscribe_stream();
+    LiveStream {
+        inner: ByteStream! {
+            loop {
+                let frame = select! {
+                    frame = receiver.recv() => frame,
+                    _ = &mut shutdown => break,
+                };
+                match frame {
+                    Ok(frame) => {
+                        let mut part = format!(
+                            "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                            STREAM_BOUNDARY,
+                            frame.len()
+                        )
+                        .into_bytes();
+                        part.extend_from_slice(&frame);
+                        part.extend_from_slice(b"\r\n");
+                        yield part;
+                    }
+                    /* a slow viewer just misses frames */
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        },
+        content_type: ContentType::new("multipart", "x-mixed-replace")
+            .with_params(("boundary", STREAM_BOUNDARY)),
+        cache_control: Header::new("Cache-Control", "no-store"),
+    }
+}
+
+/* explicitly ranked, it would collide with /gif/<project> and the client files otherwise */
+#[get("/<project>/frames?<offset>&<limit>", rank = 1)]
+pub fn frames(
+    project: u32,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Json<Option<FramePage>> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    match frame_page(project, offset.unwrap_or(0), limit) {
+        Ok(page) => Json(Some(page)),
+        Err(e) => {
+            error!("Error: {}", e);
+            Json(None)
+        }
+    }
+}
+
+#[get("/<project>/frame/<time>")]
+pub async fn frame(project: u32, time: u64) -> Option<FrameFile> {
+    FrameFile::open(&frame_path(project, time)?, "max-age=86400").await
+}
+
+/* timestamp of the frame the capture worker stored for this request */
+#[post("/snapshot")]
+pub async fn snapshot() -> Json<Option<u64>> {
+    let receiver = match request_snapshot() {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            error!("Error: {}", e);
+            return Json(None);
+        }
+    };
+    let result = spawn_blocking(move || receiver.recv_timeout(SNAPSHOT_TIMEOUT)).await;
+    Json(match result {
+        Ok(Ok(Ok(path))) => frame_time(&path),
+        Ok(Ok(Err(e))) => {
+            error!("Error: {}", e);
+            None
+        }
+        Ok(Err(e)) => {
+            error!("Error: {}", e);
+            None
+        }
+        Err(e) => {
+            error!("Error: {}", e);
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(header: &str, size: u64) -> Result<Option<(u64, u64)>, u64> {
+        ByteRange(Some(String::from(header))).resolve(size)
+    }
+
+    #[test]
+    fn single_ranges_are_resolved() {
+        assert_eq!(resolve("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(resolve("bytes=500-", 1000), Ok(Some((500, 999))));
+        assert_eq!(resolve("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(resolve("bytes=900-5000", 1000), Ok(Some((900, 999))));
+    }
+
+    #[test]
+    fn missing_or_multiple_ranges_serve_the_whole_file() {
+        assert_eq!(ByteRange(None).resolve(1000), Ok(None));
+        assert_eq!(resolve("bytes=0-1,5-6", 1000), Ok(None));
+        assert_eq!(resolve("items=0-1", 1000), Ok(None));
+        assert_eq!(resolve("bytes=a-b", 1000), Ok(None));
+    }
+
+    #[test]
+    fn ranges_outside_the_file_are_unsatisfiable() {
+        assert_eq!(resolve("bytes=1000-", 1000), Err(1000));
+        assert_eq!(resolve("bytes=50-10", 1000), Err(1000));
+        assert_eq!(resolve("bytes=0-", 0), Err(0));
+    }
+
+    #[test]
+    fn long_ranges_are_cut() {
+        let size = MAX_RANGE_LENGTH * 3;
+        assert_eq!(
+            resolve("bytes=0-", size),
+            Ok(Some((0, MAX_RANGE_LENGTH - 1)))
+        );
     }
 }
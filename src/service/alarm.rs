@@ -0,0 +1,79 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::Mutex,
+};
+
+use super::indicator::ChamberState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmKind {
+    WorkerFault,
+    StaleSensor,
+    Contamination,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alarm {
+    pub kind: AlarmKind,
+    pub since: u64,
+    pub acknowledged: bool,
+}
+
+lazy_static! {
+    static ref ALARMS: Mutex<BTreeMap<AlarmKind, Alarm>> = Mutex::new(BTreeMap::new());
+}
+
+/* raise alarms for conditions that started and drop those that are gone */
+pub fn update(state: &ChamberState, now: u64) {
+    set(AlarmKind::WorkerFault, state.fault, now);
+    set(AlarmKind::StaleSensor, state.stale_sensor, now);
+    set(AlarmKind::Contamination, state.contamination, now);
+}
+
+fn set(kind: AlarmKind, active: bool, now: u64) {
+    if let Ok(mut alarms) = ALARMS.lock() {
+        if !active {
+            alarms.remove(&kind);
+        } else if let Entry::Vacant(entry) = alarms.entry(kind) {
+            warn!("alarm raised: {:?}", kind);
+            entry.insert(Alarm {
+                kind,
+                since: now,
+                acknowledged: false,
+            });
+        }
+    }
+}
+
+pub fn active_alarms() -> Vec<Alarm> {
+    match ALARMS.lock() {
+        Ok(alarms) => alarms.values().cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn has_unacknowledged() -> bool {
+    active_alarms().iter().any(|alarm| !alarm.acknowledged)
+}
+
+/* alarms stay listed until their condition is gone, acknowledging only silences them */
+pub fn acknowledge_all() -> Result<usize, Box<dyn std::error::Error>> {
+    match ALARMS.lock() {
+        Ok(mut alarms) => {
+            let mut acknowledged = 0;
+            for alarm in alarms.values_mut().filter(|alarm| !alarm.acknowledged) {
+                alarm.acknowledged = true;
+                acknowledged += 1;
+            }
+            info!("acknowledged {} alarms", acknowledged);
+            Ok(acknowledged)
+        }
+        Err(e) => {
+            error!("Error: {}", e);
+            Err(Box::from("Alarms"))
+        }
+    }
+}
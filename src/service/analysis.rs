@@ -0,0 +1,328 @@
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use super::config::{self, seconds};
+
+/* part of the frame that is analysed, as fractions of its width and height */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalysisSettings {
+    pub enabled: bool,
+    /* at most one frame per interval is analysed */
+    #[serde(with = "seconds")]
+    pub interval: Duration,
+    pub region: Region,
+    /* white: bright and hardly saturated, values from 0 to 1 */
+    pub white_min_value: f32,
+    pub white_max_saturation: f32,
+    /* black: dark regardless of colour */
+    pub black_max_value: f32,
+    /* green: hue between 70° and 170° with some saturation */
+    pub green_min_saturation: f32,
+    /* alarm once the green and black share grew this much over the first analysed frame of the
+    project, e.g. 0.05 for 5 percentage points; no alarm when unset */
+    pub contamination_threshold: Option<f32>,
+}
+
+impl Default for AnalysisSettings {
+    fn default() -> Self {
+        AnalysisSettings {
+            enabled: false,
+            interval: Duration::from_secs(60),
+            region: Region {
+                x: 0.0,
+                y: 0.0,
+                width: 1.0,
+                height: 1.0,
+            },
+            white_min_value: 0.7,
+            white_max_saturation: 0.2,
+            black_max_value: 0.15,
+            green_min_saturation: 0.25,
+            contamination_threshold: None,
+        }
+    }
+}
+
+/* share of the region's pixels in each colour class */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameAnalysis {
+    pub time: u64,
+    /* white and near-white, the mycelium */
+    pub coverage: f32,
+    pub green: f32,
+    pub black: f32,
+}
+
+/* analysing every pixel of a 1080p frame buys nothing over a sample of this size */
+const MAX_SAMPLES: u32 = 100_000;
+const GREEN_HUE: std::ops::Range<f32> = 70.0..170.0;
+
+/* the project whose contamination alarm is raised, it must not carry over to the next one */
+static CONTAMINATED: Mutex<Option<u32>> = Mutex::new(None);
+static LAST_ANALYSIS: AtomicU64 = AtomicU64::new(0);
+
+pub fn get_settings() -> &'static AnalysisSettings {
+    &config::get().analysis
+}
+
+pub fn contamination_detected() -> bool {
+    match CONTAMINATED.lock() {
+        Ok(contaminated) => contaminated.is_some(),
+        Err(_) => false,
+    }
+}
+
+/* called with the active project, the alarm of any other project is dropped */
+pub fn track_project(active: Option<u32>) {
+    if let Ok(mut contaminated) = CONTAMINATED.lock() {
+        if contaminated.is_some() && *contaminated != active {
+            info!("contamination alarm of project {:?} cleared", *contaminated);
+            *contaminated = None;
+        }
+    }
+}
+
+/* growth of the green and black share over the first analysed frame beyond the threshold */
+fn is_contaminated(baseline: &FrameAnalysis, analysis: &FrameAnalysis, threshold: f32) -> bool {
+    (analysis.green + analysis.black) - (baseline.green + baseline.black) > threshold
+}
+
+/* called for every captured frame before the overlay is drawn onto it */
+pub fn frame_captured(
+    project: u32,
+    frame: &RgbImage,
+    time: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = get_settings();
+    if !settings.enabled
+        || time.saturating_sub(LAST_ANALYSIS.load(Ordering::SeqCst)) < settings.interval.as_secs()
+    {
+        return Ok(());
+    }
+    LAST_ANALYSIS.store(time, Ordering::SeqCst);
+    add_analysis(project, analyse(frame, time, settings))
+}
+
+pub fn analyse(frame: &RgbImage, time: u64, settings: &AnalysisSettings) -> FrameAnalysis {
+    let region = &settings.region;
+    let x = (region.x * frame.width() as f32) as u32;
+    let y = (region.y * frame.height() as f32) as u32;
+    let width = ((region.width * frame.width() as f32) as u32)
+        .min(frame.width().saturating_sub(x))
+        .max(1);
+    let height = ((region.height * frame.height() as f32) as u32)
+        .min(frame.height().saturating_sub(y))
+        .max(1);
+    let step = ((width as f64 * height as f64 / MAX_SAMPLES as f64)
+        .sqrt()
+        .ceil() as u32)
+        .max(1);
+
+    let (mut white, mut green, mut black, mut total) = (0u32, 0u32, 0u32, 0u32);
+    for y in (y..y + height).step_by(step as usize) {
+        for x in (x..x + width).step_by(step as usize) {
+            let [r, g, b] = frame.get_pixel(x, y).0;
+            let (hue, saturation, value) = hsv(r, g, b);
+            if value <= settings.black_max_value {
+                black += 1;
+            } else if value >= settings.white_min_value
+                && saturation <= settings.white_max_saturation
+            {
+                white += 1;
+            } else if saturation >= settings.green_min_saturation && GREEN_HUE.contains(&hue) {
+                green += 1;
+            }
+            total += 1;
+        }
+    }
+    let total = total.max(1) as f32;
+    FrameAnalysis {
+        time,
+        coverage: white as f32 / total,
+        green: green as f32 / total,
+        black: black as f32 / total,
+    }
+}
+
+/* hue in degrees, saturation and value from 0 to 1 */
+fn hsv(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, saturation, max)
+}
+
+/* read analysis json path: <db>/analysis/<project>.json */
+pub fn read_analysis(project: u32) -> Result<Vec<FrameAnalysis>, Box<dyn std::error::Error>> {
+    let path = config::get().paths.analysis_file(project);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut file = File::open(path)?;
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+/* store the analysis of a new frame and update the contamination alarm; the series is replaced
+in one rename so a power cut while writing leaves the previous one */
+pub fn add_analysis(
+    project: u32,
+    analysis: FrameAnalysis,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config::get().paths.analysis_file(project);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut series = read_analysis(project)?;
+    series.push(analysis);
+    let tmp_path = path.with_extension("json.tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(serde_json::to_string(&series)?.as_bytes())?;
+    fs::rename(tmp_path, path)?;
+
+    if let (Some(threshold), Some(baseline)) =
+        (get_settings().contamination_threshold, series.first())
+    {
+        set_contaminated(project, is_contaminated(baseline, &analysis, threshold));
+    }
+    Ok(())
+}
+
+fn set_contaminated(project: u32, contaminated: bool) {
+    if let Ok(mut current) = CONTAMINATED.lock() {
+        if contaminated && *current != Some(project) {
+            warn!(
+                "contamination coloured area of project {} grew beyond the threshold",
+                project
+            );
+            *current = Some(project);
+        } else if !contaminated && *current == Some(project) {
+            *current = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn pixels_are_classified_by_colour() {
+        /* one row each of white, green, black and a brown that counts as none of them */
+        let frame = RgbImage::from_fn(10, 4, |_, y| match y {
+            0 => Rgb([250, 250, 245]),
+            1 => Rgb([40, 160, 60]),
+            2 => Rgb([10, 10, 10]),
+            _ => Rgb([150, 100, 50]),
+        });
+        let analysis = analyse(&frame, 7, &AnalysisSettings::default());
+        assert_eq!(analysis.time, 7);
+        assert_eq!(analysis.coverage, 0.25);
+        assert_eq!(analysis.green, 0.25);
+        assert_eq!(analysis.black, 0.25);
+    }
+
+    #[test]
+    fn only_the_region_is_analysed() {
+        let frame = RgbImage::from_fn(10, 10, |x, _| {
+            if x < 5 {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        });
+        let settings = AnalysisSettings {
+            region: Region {
+                x: 0.5,
+                y: 0.0,
+                width: 0.5,
+                height: 1.0,
+            },
+            ..AnalysisSettings::default()
+        };
+        let analysis = analyse(&frame, 0, &settings);
+        assert_eq!(analysis.coverage, 0.0);
+        assert_eq!(analysis.black, 1.0);
+    }
+
+    #[test]
+    fn contamination_is_growth_over_the_baseline() {
+        let frame = |green, black| FrameAnalysis {
+            time: 0,
+            coverage: 0.0,
+            green,
+            black,
+        };
+        /* a dark substrate at the start is no contamination */
+        assert!(!is_contaminated(&frame(0.0, 0.5), &frame(0.0, 0.5), 0.05));
+        assert!(!is_contaminated(&frame(0.0, 0.5), &frame(0.0, 0.5), 0.0));
+        assert!(is_contaminated(&frame(0.0, 0.5), &frame(0.125, 0.5), 0.05));
+    }
+
+    /* the only test touching the global alarm, so it can not race with others */
+    #[test]
+    fn alarm_belongs_to_the_contaminated_project() {
+        set_contaminated(1, true);
+        track_project(Some(1));
+        assert!(contamination_detected());
+        /* a frame of another project does not clear it */
+        set_contaminated(2, false);
+        assert!(contamination_detected());
+        track_project(Some(2));
+        assert!(!contamination_detected());
+
+        set_contaminated(2, true);
+        track_project(None);
+        assert!(!contamination_detected());
+    }
+
+    #[test]
+    fn analyses_are_appended_without_leftovers() {
+        let project = u32::MAX;
+        let path = config::get().paths.analysis_file(project);
+        let frame = |time| FrameAnalysis {
+            time,
+            coverage: 0.5,
+            green: 0.0,
+            black: 0.0,
+        };
+        add_analysis(project, frame(1)).unwrap();
+        add_analysis(project, frame(2)).unwrap();
+        let series = read_analysis(project);
+        let leftover = path.with_extension("json.tmp").exists();
+        let _ = fs::remove_file(&path);
+        assert_eq!(series.unwrap(), vec![frame(1), frame(2)]);
+        assert!(!leftover);
+    }
+}
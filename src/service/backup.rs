@@ -0,0 +1,358 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use super::config;
+
+const MANIFEST_NAME: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+/* archive names are fixed, on disk they map to the configured directories */
+const DB_DIR: &str = "db";
+const WEBCAM_DIR: &str = "webcam";
+
+/* the admin routes are disabled until a token is configured */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub token: Option<String>,
+    /* largest backup accepted by POST /admin/restore */
+    pub max_upload_mb: u64,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            token: None,
+            max_upload_mb: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub created_at: u64,
+    pub includes_webcam: bool,
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestoreReport {
+    pub dry_run: bool,
+    pub created_at: u64,
+    pub files: usize,
+    pub bytes: u64,
+    pub errors: Vec<String>,
+}
+
+/* write db and optionally webcam frames as tar.zst, manifest last; the sensor file of today
+and the frame directory change while the backup runs, so every file is read once and the
+checksum is taken from exactly the bytes that go into the archive */
+pub fn create_backup(
+    writer: impl Write,
+    include_webcam: bool,
+) -> Result<Manifest, Box<dyn std::error::Error>> {
+    let dirs = &config::get().paths;
+    let mut paths = Vec::new();
+    collect_files(&dirs.db, DB_DIR, &mut paths)?;
+    if include_webcam {
+        collect_files(&dirs.webcam, WEBCAM_DIR, &mut paths)?;
+    }
+    let created_at = chrono::Utc::now().timestamp() as u64;
+
+    let encoder = zstd::Encoder::new(writer, 3)?;
+    let mut archive = tar::Builder::new(encoder);
+    let mut files = Vec::new();
+    for (path, name) in paths.iter() {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            /* removed since it was listed, e.g. by retention */
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(Box::from(e)),
+        };
+        let (size, sha256) = checksum(&mut data.as_slice())?;
+        append_data(&mut archive, name, &data, created_at)?;
+        files.push(ManifestEntry {
+            path: name.clone(),
+            size,
+            sha256,
+        });
+    }
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        created_at,
+        includes_webcam: include_webcam,
+        files,
+    };
+    let manifest_data = serde_json::to_vec_pretty(&manifest)?;
+    append_data(&mut archive, MANIFEST_NAME, &manifest_data, created_at)?;
+    archive.into_inner()?.finish()?;
+    Ok(manifest)
+}
+
+fn append_data(
+    archive: &mut tar::Builder<impl Write>,
+    name: &str,
+    data: &[u8],
+    mtime: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    archive.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/* unpack into a staging directory, verify against the manifest and only then move into place.
+The workers write to the same files, so a real restore is only done while the service is stopped.
+The staging directory sits next to the db directory so the files are renamed on one filesystem */
+pub fn restore_backup(
+    reader: impl Read,
+    dry_run: bool,
+) -> Result<RestoreReport, Box<dyn std::error::Error>> {
+    let staging = config::get().paths.db.with_file_name(format!(
+        ".restore-{}",
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    ));
+    let result = unpack_and_verify(reader, &staging, dry_run);
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn unpack_and_verify(
+    reader: impl Read,
+    staging: &Path,
+    dry_run: bool,
+) -> Result<RestoreReport, Box<dyn std::error::Error>> {
+    fs::create_dir_all(staging)?;
+    let mut archive = tar::Archive::new(zstd::Decoder::new(reader)?);
+    archive.unpack(staging)?;
+
+    let manifest: Manifest = serde_json::from_reader(File::open(staging.join(MANIFEST_NAME))?)?;
+    if manifest.version != MANIFEST_VERSION {
+        return Err(Box::from(format!(
+            "Unsupported backup version {}",
+            manifest.version
+        )));
+    }
+
+    let mut report = RestoreReport {
+        dry_run,
+        created_at: manifest.created_at,
+        files: manifest.files.len(),
+        bytes: 0,
+        errors: Vec::new(),
+    };
+    for entry in manifest.files.iter() {
+        if target_path(&entry.path).is_none() {
+            report
+                .errors
+                .push(format!("{}: unexpected path", entry.path));
+            continue;
+        }
+        let mut file = match File::open(staging.join(&entry.path)) {
+            Ok(file) => file,
+            Err(e) => {
+                report.errors.push(format!("{}: {}", entry.path, e));
+                continue;
+            }
+        };
+        let (size, sha256) = checksum(&mut file)?;
+        if size != entry.size || sha256 != entry.sha256 {
+            report
+                .errors
+                .push(format!("{}: checksum mismatch", entry.path));
+        }
+        report.bytes += size;
+    }
+
+    if dry_run || !report.errors.is_empty() {
+        return Ok(report);
+    }
+    for entry in manifest.files.iter() {
+        let target = match target_path(&entry.path) {
+            Some(target) => target,
+            None => continue,
+        };
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_file(&staging.join(&entry.path), &target)?;
+    }
+    Ok(report)
+}
+
+/* the webcam directory may be on another filesystem than the staging directory, a rename fails
+there and the file is copied next to its target first */
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_into_place(from, to),
+        result => result,
+    }
+}
+
+fn copy_into_place(from: &Path, to: &Path) -> io::Result<()> {
+    let mut partial = to.as_os_str().to_owned();
+    partial.push(".part");
+    if let Err(e) = fs::copy(from, &partial).and_then(|_| fs::rename(&partial, to)) {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::remove_file(from)
+}
+
+/* files below dir together with their name in the archive */
+fn collect_files(
+    dir: &Path,
+    name: &str,
+    paths: &mut Vec<(PathBuf, String)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.path());
+    for entry in entries {
+        let path = entry.path();
+        let entry_name = match entry.file_name().to_str() {
+            Some(file_name) => format!("{}/{}", name, file_name),
+            None => return Err(Box::from("Invalid path")),
+        };
+        if path.is_dir() {
+            collect_files(&path, &entry_name, paths)?;
+        } else {
+            paths.push((path, entry_name));
+        }
+    }
+    Ok(())
+}
+
+/* where an archive entry is restored to, None for anything outside db/ and webcam/; names
+come from an uploaded archive, so only plain relative paths are accepted */
+fn target_path(name: &str) -> Option<PathBuf> {
+    let plain = Path::new(name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !plain {
+        return None;
+    }
+    let dirs = &config::get().paths;
+    if let Some(rest) = name.strip_prefix(&format!("{DB_DIR}/")) {
+        Some(dirs.db.join(rest))
+    } else {
+        name.strip_prefix(&format!("{WEBCAM_DIR}/"))
+            .map(|rest| dirs.webcam.join(rest))
+    }
+}
+
+fn checksum(reader: &mut impl Read) -> Result<(u64, String), Box<dyn std::error::Error>> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(reader, &mut hasher)?;
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(files: &[(&str, &[u8])], manifest: &Manifest) -> Vec<u8> {
+        let mut builder = tar::Builder::new(zstd::Encoder::new(Vec::new(), 3).unwrap());
+        for (name, data) in files {
+            append_data(&mut builder, name, data, 0).unwrap();
+        }
+        let manifest = serde_json::to_vec(manifest).unwrap();
+        append_data(&mut builder, MANIFEST_NAME, &manifest, 0).unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn manifest(entries: &[(&str, &[u8])]) -> Manifest {
+        Manifest {
+            version: MANIFEST_VERSION,
+            created_at: 0,
+            includes_webcam: false,
+            files: entries
+                .iter()
+                .map(|(path, data)| {
+                    let (size, sha256) = checksum(&mut &data[..]).unwrap();
+                    ManifestEntry {
+                        path: path.to_string(),
+                        size,
+                        sha256,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn verify(data: &[u8]) -> RestoreReport {
+        let staging = std::env::temp_dir().join(format!(
+            "fermentation-backup-test-{}",
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let report = unpack_and_verify(data, &staging, true).unwrap();
+        let _ = fs::remove_dir_all(&staging);
+        report
+    }
+
+    #[test]
+    fn matching_archive_verifies() {
+        let files: [(&str, &[u8]); 2] = [
+            ("db/projects.json", b"[]"),
+            ("db/sensor/2024-01-01.json", b"[]"),
+        ];
+        let report = verify(&archive(&files, &manifest(&files)));
+        assert_eq!(report.errors, Vec::<String>::new());
+        assert_eq!(report.files, 2);
+        assert_eq!(report.bytes, 4);
+    }
+
+    #[test]
+    fn changed_file_is_rejected() {
+        let listed: [(&str, &[u8]); 1] = [("db/projects.json", b"[]")];
+        let packed: [(&str, &[u8]); 1] = [("db/projects.json", b"[{}]")];
+        let report = verify(&archive(&packed, &manifest(&listed)));
+        assert_eq!(report.errors, vec!["db/projects.json: checksum mismatch"]);
+    }
+
+    #[test]
+    fn paths_outside_the_data_directories_are_rejected() {
+        assert!(target_path("db/projects.json").is_some());
+        assert!(target_path("webcam/7/2024-01-01/1704067200.jpg").is_some());
+        assert!(target_path("db/../../etc/passwd").is_none());
+        assert!(target_path("webcam/../db/projects.json").is_none());
+        assert!(target_path("/etc/passwd").is_none());
+        assert!(target_path("tmp/file").is_none());
+
+        let listed: [(&str, &[u8]); 1] = [("db/../../outside", b"x")];
+        let report = verify(&archive(&[], &manifest(&listed)));
+        assert_eq!(report.errors, vec!["db/../../outside: unexpected path"]);
+    }
+
+    #[test]
+    fn copied_file_replaces_the_target() {
+        let dir = std::env::temp_dir().join(format!("backup-copy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (from, to) = (dir.join("staged.json"), dir.join("projects.json"));
+        fs::write(&from, b"new").unwrap();
+        fs::write(&to, b"old").unwrap();
+        copy_into_place(&from, &to).unwrap();
+        let content = fs::read(&to).unwrap();
+        let leftovers = fs::read_dir(&dir).unwrap().count();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(content, b"new");
+        assert_eq!(leftovers, 1);
+    }
+}
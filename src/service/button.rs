@@ -0,0 +1,200 @@
+use std::time::{Duration, Instant};
+
+use super::gpio::ButtonConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    Short,
+    Long,
+    Double,
+}
+
+/* turns the reported levels of one button into presses. A level only counts once it stayed
+the same for the debounce time, so the last edge of a bounce burst always wins, even when it
+is the real release. A short press is only reported once the double press window passed
+without a second press. */
+pub struct ButtonClassifier {
+    debounce: Duration,
+    long_press: Duration,
+    double_press: Duration,
+    /* debounced level */
+    pressed: bool,
+    /* last reported level and when it was reported */
+    level: bool,
+    level_since: Option<Instant>,
+    pressed_at: Option<Instant>,
+    pending_short: Option<Instant>,
+}
+
+impl ButtonClassifier {
+    pub fn new(config: &ButtonConfig) -> Self {
+        ButtonClassifier {
+            debounce: config.debounce,
+            long_press: config.long_press,
+            double_press: config.double_press,
+            pressed: false,
+            level: false,
+            level_since: None,
+            pressed_at: None,
+            pending_short: None,
+        }
+    }
+
+    pub fn edge(&mut self, pressed: bool, at: Instant) -> Option<Press> {
+        let press = self.settle(at);
+        if pressed != self.level {
+            self.level = pressed;
+            self.level_since = Some(at);
+        }
+        press
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Option<Press> {
+        if let Some(press) = self.settle(now) {
+            return Some(press);
+        }
+        match self.pending_short {
+            Some(released_at)
+                if !self.pressed
+                    && now.saturating_duration_since(released_at) >= self.double_press =>
+            {
+                self.pending_short = None;
+                Some(Press::Short)
+            }
+            _ => None,
+        }
+    }
+
+    /* take over the reported level once it was stable long enough, dated to its edge */
+    fn settle(&mut self, now: Instant) -> Option<Press> {
+        let since = self.level_since?;
+        if self.level == self.pressed || now.saturating_duration_since(since) < self.debounce {
+            return None;
+        }
+        self.pressed = self.level;
+        if self.pressed {
+            self.pressed_at = Some(since);
+            /* a press after the double press window ends the previous short press */
+            return match self.pending_short {
+                Some(released_at)
+                    if since.saturating_duration_since(released_at) >= self.double_press =>
+                {
+                    self.pending_short = None;
+                    Some(Press::Short)
+                }
+                _ => None,
+            };
+        }
+        let held = since.saturating_duration_since(self.pressed_at.take()?);
+        if held >= self.long_press {
+            self.pending_short = None;
+            Some(Press::Long)
+        } else if self.pending_short.take().is_some() {
+            Some(Press::Double)
+        } else {
+            self.pending_short = Some(since);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBOUNCE: Duration = Duration::from_millis(50);
+
+    fn classifier() -> ButtonClassifier {
+        ButtonClassifier {
+            debounce: DEBOUNCE,
+            long_press: Duration::from_secs(2),
+            double_press: Duration::from_millis(400),
+            pressed: false,
+            level: false,
+            level_since: None,
+            pressed_at: None,
+            pending_short: None,
+        }
+    }
+
+    /* feeds (milliseconds, level) edges and polls every 10 ms up to `until` */
+    fn presses(edges: &[(u64, bool)], until: u64) -> Vec<(u64, Press)> {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut classifier = classifier();
+        let mut presses = Vec::new();
+        let mut edges = edges.iter().peekable();
+        for ms in (0..=until).step_by(10) {
+            while let Some((edge_ms, level)) = edges.next_if(|(edge_ms, _)| *edge_ms <= ms) {
+                if let Some(press) = classifier.edge(*level, at(*edge_ms)) {
+                    presses.push((ms, press));
+                }
+            }
+            if let Some(press) = classifier.poll(at(ms)) {
+                presses.push((ms, press));
+            }
+        }
+        presses
+    }
+
+    fn kinds(presses: &[(u64, Press)]) -> Vec<Press> {
+        presses.iter().map(|(_, press)| *press).collect()
+    }
+
+    #[test]
+    fn short_press_waits_for_the_double_press_window() {
+        let presses = presses(&[(0, true), (200, false)], 1000);
+        assert_eq!(kinds(&presses), vec![Press::Short]);
+        assert!(presses[0].0 >= 600);
+    }
+
+    #[test]
+    fn long_press() {
+        let presses = presses(&[(0, true), (2500, false)], 3500);
+        assert_eq!(kinds(&presses), vec![Press::Long]);
+    }
+
+    #[test]
+    fn double_press() {
+        let presses = presses(&[(0, true), (150, false), (300, true), (450, false)], 1500);
+        assert_eq!(kinds(&presses), vec![Press::Double]);
+    }
+
+    #[test]
+    fn two_presses_far_apart_are_two_short_presses() {
+        let presses = presses(
+            &[(0, true), (150, false), (1000, true), (1150, false)],
+            2000,
+        );
+        assert_eq!(kinds(&presses), vec![Press::Short, Press::Short]);
+    }
+
+    #[test]
+    fn bouncing_contacts_count_once() {
+        let presses = presses(
+            &[
+                (0, true),
+                (3, false),
+                (6, true),
+                (200, false),
+                (204, true),
+                (208, false),
+            ],
+            1000,
+        );
+        assert_eq!(kinds(&presses), vec![Press::Short]);
+    }
+
+    /* the release 20 ms after the press lies within the debounce time, it must still end the
+    press instead of turning the next press into a long one */
+    #[test]
+    fn release_within_debounce_is_not_lost() {
+        let presses = presses(&[(0, true), (20, false), (3000, true), (3150, false)], 4000);
+        assert!(!kinds(&presses).contains(&Press::Long));
+    }
+
+    #[test]
+    fn glitch_shorter_than_debounce_is_ignored() {
+        assert_eq!(presses(&[(0, true), (20, false)], 1000), Vec::new());
+    }
+}
@@ -0,0 +1,523 @@
+use rocket::figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{path::PathBuf, sync::OnceLock, time::Duration};
+
+use super::{
+    analysis::AnalysisSettings,
+    backup::AdminConfig,
+    gpio::{self, GpioConfig},
+    indicator::IndicatorConfig,
+    influx::InfluxConfig,
+    output::OutputConfig,
+    overlay::OverlaySettings,
+    retention::RetentionPolicy,
+    timelapse::TimelapseSettings,
+    webcam::{CaptureFormat, CaptureSettings, StorageFormat},
+};
+
+const DEFAULT_CONFIG_PATH: &str = "Fermentation.toml";
+const ENV_PREFIX: &str = "FERMENTATION_";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub paths: PathConfig,
+    pub sensor: SensorConfig,
+    pub control: ControlConfig,
+    pub webcam: CaptureSettings,
+    pub timelapse: TimelapseSettings,
+    pub overlay: OverlaySettings,
+    pub analysis: AnalysisSettings,
+    pub retention: RetentionPolicy,
+    pub influx: Option<InfluxConfig>,
+    pub gpio: GpioConfig,
+    pub indicator: IndicatorConfig,
+    pub admin: AdminConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathConfig {
+    /* projects.json and the sensor day files */
+    pub db: PathBuf,
+    /* one directory of frames per project */
+    pub webcam: PathBuf,
+    /* built web client served at / */
+    pub client: PathBuf,
+    /* scratch space for rendered timelapses */
+    pub tmp: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensorConfig {
+    pub max_retries: u8,
+    pub min_temp: f32,
+    pub max_temp: f32,
+    pub min_hum: f32,
+    pub max_hum: f32,
+    #[serde(with = "seconds")]
+    pub log_interval: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlConfig {
+    #[serde(with = "seconds")]
+    pub temp_interval: Duration,
+    #[serde(with = "seconds")]
+    pub hum_interval: Duration,
+    /* neither heating nor cooling while the temperature is within half of this around the target */
+    pub temp_deadband: f32,
+    pub heating_pid: PidTuning,
+    pub cooling_pid: PidTuning,
+    /* neither humidifier nor fan while the humidity is within half of this around the target */
+    pub hum_deadband: f32,
+    pub humidifier_pid: PidTuning,
+    pub fan_pid: PidTuning,
+    pub fresh_air: FreshAirSchedule,
+    pub heating: OutputConfig,
+    /* keys missing here fall back to the compressor defaults, not to those of heating */
+    #[serde(deserialize_with = "compressor_output")]
+    pub cooling: OutputConfig,
+    pub humidifier: OutputConfig,
+    pub fan: OutputConfig,
+}
+
+/* periodic air exchange, disabled while interval or duration is 0 */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FreshAirSchedule {
+    #[serde(with = "seconds")]
+    pub interval: Duration,
+    #[serde(with = "seconds")]
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PidTuning {
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+}
+
+impl Default for PathConfig {
+    fn default() -> Self {
+        PathConfig {
+            db: PathBuf::from("./db"),
+            webcam: PathBuf::from("./webcam"),
+            client: PathBuf::from("fermentation-pi-client/dist"),
+            tmp: PathBuf::from("tmp"),
+        }
+    }
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        SensorConfig {
+            max_retries: 5,
+            min_temp: 0.0,
+            max_temp: 50.0,
+            min_hum: 0.0,
+            max_hum: 100.0,
+            log_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            temp_interval: Duration::from_secs(1),
+            hum_interval: Duration::from_secs(1),
+            temp_deadband: 1.0,
+            heating_pid: PidTuning::default(),
+            cooling_pid: PidTuning::default(),
+            hum_deadband: 4.0,
+            humidifier_pid: PidTuning::default(),
+            fan_pid: PidTuning::default(),
+            fresh_air: FreshAirSchedule::default(),
+            heating: OutputConfig::default(),
+            cooling: OutputConfig::compressor(),
+            humidifier: OutputConfig::default(),
+            fan: OutputConfig::default(),
+        }
+    }
+}
+
+impl Default for FreshAirSchedule {
+    fn default() -> Self {
+        FreshAirSchedule {
+            interval: Duration::ZERO,
+            duration: Duration::from_secs(120),
+        }
+    }
+}
+
+impl Default for PidTuning {
+    fn default() -> Self {
+        PidTuning {
+            p: 35.0,
+            i: 0.09,
+            d: 10.0,
+        }
+    }
+}
+
+impl PathConfig {
+    pub fn projects_file(&self) -> PathBuf {
+        self.db.join("projects.json")
+    }
+
+    pub fn sensor_dir(&self) -> PathBuf {
+        self.db.join("sensor")
+    }
+
+    pub fn sensor_file(&self, date: chrono::NaiveDate) -> PathBuf {
+        self.sensor_dir()
+            .join(format!("{}.json", date.format("%Y-%m-%d")))
+    }
+
+    pub fn webcam_dir(&self, project: u32) -> PathBuf {
+        self.webcam.join(project.to_string())
+    }
+
+    pub fn analysis_file(&self, project: u32) -> PathBuf {
+        self.db.join("analysis").join(format!("{}.json", project))
+    }
+}
+
+impl Config {
+    /* collect every problem instead of stopping at the first one */
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut errors = Vec::new();
+        if self.sensor.max_retries == 0 {
+            errors.push(String::from("sensor.max_retries must be at least 1"));
+        }
+        if self.sensor.min_temp >= self.sensor.max_temp {
+            errors.push(String::from(
+                "sensor.min_temp must be below sensor.max_temp",
+            ));
+        }
+        if self.sensor.min_hum >= self.sensor.max_hum {
+            errors.push(String::from("sensor.min_hum must be below sensor.max_hum"));
+        }
+        for (name, interval) in [
+            ("sensor.log_interval", self.sensor.log_interval),
+            ("control.temp_interval", self.control.temp_interval),
+            ("control.hum_interval", self.control.hum_interval),
+            ("webcam.interval", self.webcam.interval),
+        ] {
+            if interval.is_zero() {
+                errors.push(format!("{} must be greater than 0", name));
+            }
+        }
+        for (name, output) in [
+            ("control.heating", &self.control.heating),
+            ("control.cooling", &self.control.cooling),
+            ("control.humidifier", &self.control.humidifier),
+            ("control.fan", &self.control.fan),
+        ] {
+            if output.window.is_zero() {
+                errors.push(format!("{}.window must be greater than 0", name));
+            }
+            if output.min_on + output.min_off > output.window {
+                errors.push(format!(
+                    "{}.min_on and min_off must fit into the window",
+                    name
+                ));
+            }
+        }
+        if self.control.temp_deadband < 0.0 {
+            errors.push(String::from("control.temp_deadband must not be negative"));
+        }
+        if self.control.hum_deadband < 0.0 {
+            errors.push(String::from("control.hum_deadband must not be negative"));
+        }
+        if self.webcam.width == 0 || self.webcam.height == 0 || self.webcam.fps == 0 {
+            errors.push(String::from(
+                "webcam.width, height and fps must be greater than 0",
+            ));
+        }
+        let stream = &self.webcam.stream;
+        if stream.width == 0 || stream.height == 0 || stream.fps == 0 {
+            errors.push(String::from(
+                "webcam.stream.width, height and fps must be greater than 0",
+            ));
+        }
+        if !(1..=100).contains(&stream.quality) {
+            errors.push(String::from(
+                "webcam.stream.quality must be between 1 and 100",
+            ));
+        }
+        let storage = &self.webcam.storage;
+        if !(1..=100).contains(&storage.quality) {
+            errors.push(String::from(
+                "webcam.storage.quality must be between 1 and 100",
+            ));
+        }
+        if storage.max_width == Some(0) || storage.max_height == Some(0) {
+            errors.push(String::from(
+                "webcam.storage.max_width and max_height must be greater than 0",
+            ));
+        }
+        /* the camera's JPEG is stored as is, there is nothing to scale or draw on */
+        if storage.format == StorageFormat::Mjpeg {
+            if self.webcam.format != CaptureFormat::Mjpeg {
+                errors.push(String::from(
+                    "webcam.storage.format mjpeg needs webcam.format mjpeg",
+                ));
+            }
+            if storage.max_width.is_some() || storage.max_height.is_some() || self.overlay.frames {
+                errors.push(String::from(
+                    "webcam.storage.format mjpeg can't be combined with max_width, max_height or overlay.frames",
+                ));
+            }
+        }
+        if self.timelapse.frames == 0 || self.timelapse.width == 0 || self.timelapse.height == 0 {
+            errors.push(String::from(
+                "timelapse.frames, width and height must be greater than 0",
+            ));
+        }
+        if !(1..=100).contains(&self.timelapse.fps) {
+            errors.push(String::from("timelapse.fps must be between 1 and 100"));
+        }
+        if self.timelapse.frames > self.timelapse.max_frames
+            || self.timelapse.width > self.timelapse.max_width
+            || self.timelapse.height > self.timelapse.max_height
+        {
+            errors.push(String::from(
+                "timelapse.frames, width and height must not exceed their max_ settings",
+            ));
+        }
+        if self.timelapse.max_gif_megapixels == 0 {
+            errors.push(String::from(
+                "timelapse.max_gif_megapixels must be greater than 0",
+            ));
+        }
+        if self.admin.max_upload_mb == 0 {
+            errors.push(String::from("admin.max_upload_mb must be greater than 0"));
+        }
+        if self.admin.token.as_deref() == Some("") {
+            errors.push(String::from(
+                "admin.token must not be empty, leave it out to disable the admin routes",
+            ));
+        }
+        if !(1..=16).contains(&self.overlay.scale) {
+            errors.push(String::from("overlay.scale must be between 1 and 16"));
+        }
+        let analysis = &self.analysis;
+        if analysis.interval.is_zero() {
+            errors.push(String::from("analysis.interval must be greater than 0"));
+        }
+        let region = &analysis.region;
+        if region.x < 0.0
+            || region.y < 0.0
+            || region.width <= 0.0
+            || region.height <= 0.0
+            || region.x + region.width > 1.0
+            || region.y + region.height > 1.0
+        {
+            errors.push(String::from(
+                "analysis.region must lie within the frame, as fractions from 0 to 1",
+            ));
+        }
+        for (name, value) in [
+            ("analysis.white_min_value", analysis.white_min_value),
+            (
+                "analysis.white_max_saturation",
+                analysis.white_max_saturation,
+            ),
+            ("analysis.black_max_value", analysis.black_max_value),
+            (
+                "analysis.green_min_saturation",
+                analysis.green_min_saturation,
+            ),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                errors.push(format!("{} must be between 0 and 1", name));
+            }
+        }
+        if let Some(threshold) = analysis.contamination_threshold {
+            if !(threshold > 0.0 && threshold <= 1.0) {
+                errors.push(String::from(
+                    "analysis.contamination_threshold must be above 0 and at most 1",
+                ));
+            }
+        }
+        if self.retention.minute_days < self.retention.raw_days {
+            errors.push(String::from(
+                "retention.minute_days must not be below retention.raw_days",
+            ));
+        }
+        if let Some(influx) = self.influx.as_ref() {
+            if influx.url.is_empty() {
+                errors.push(String::from("influx.url must not be empty"));
+            }
+        }
+        for (name, rules) in [
+            ("indicator.led1", &self.indicator.led1),
+            ("indicator.led2", &self.indicator.led2),
+            ("indicator.led3", &self.indicator.led3),
+        ] {
+            if rules.iter().any(|rule| rule.on.is_zero()) {
+                errors.push(format!("{} rules need an on time greater than 0", name));
+            }
+        }
+        if let Err(e) = gpio::validate_config(&self.gpio) {
+            errors.push(format!("gpio: {}", e));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Box::from(errors.join(", ")))
+        }
+    }
+
+    /* the config as served by /config, without secrets */
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        if let Some(influx) = config.influx.as_mut() {
+            if !influx.token.is_empty() {
+                influx.token = String::from("***");
+            }
+        }
+        if config.admin.token.is_some() {
+            config.admin.token = Some(String::from("***"));
+        }
+        config
+    }
+}
+
+/* OutputConfig fills missing keys with its own defaults, which would drop the compressor
+protection of a partial [control.cooling] table; the given keys are laid over the compressor
+defaults instead */
+fn compressor_output<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OutputConfig, D::Error> {
+    let overrides = serde_json::Map::deserialize(deserializer)?;
+    let mut config = serde_json::to_value(OutputConfig::compressor()).map_err(de::Error::custom)?;
+    if let serde_json::Value::Object(fields) = &mut config {
+        fields.extend(overrides);
+    }
+    serde_json::from_value(config).map_err(de::Error::custom)
+}
+
+/* Rocket's own figment plus Fermentation.toml (or FERMENTATION_CONFIG) and FERMENTATION_*
+variables, nested keys are separated by a double underscore, e.g. FERMENTATION_PATHS__DB */
+pub fn figment() -> Figment {
+    rocket::Config::figment()
+        .merge(Toml::file(Env::var_or(
+            "FERMENTATION_CONFIG",
+            DEFAULT_CONFIG_PATH,
+        )))
+        .merge(Env::prefixed(ENV_PREFIX).split("__").ignore(&["config"]))
+}
+
+pub fn load(figment: &Figment) -> Result<Config, Box<dyn std::error::Error>> {
+    let config: Config = figment.extract()?;
+    config.validate()?;
+    Ok(config)
+}
+
+/* has to run before any worker starts, later calls are ignored */
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        error!("Error: config already initialized");
+    }
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/* durations are written as (fractional) seconds in the config file */
+pub mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let seconds = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_toml(toml: &str) -> Result<Config, Box<dyn std::error::Error>> {
+        load(&Figment::new().merge(Toml::string(toml)))
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(Config::default().validate().is_ok());
+        assert_eq!(from_toml("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn partial_cooling_table_keeps_compressor_defaults() {
+        let config = from_toml("[control.cooling]\nwindow = 900").unwrap();
+        let cooling = config.control.cooling;
+        assert_eq!(cooling.window, Duration::from_secs(900));
+        assert_eq!(cooling.min_on, OutputConfig::compressor().min_on);
+        assert_eq!(cooling.min_off, OutputConfig::compressor().min_off);
+        assert_eq!(
+            cooling.max_switches_per_hour,
+            OutputConfig::compressor().max_switches_per_hour
+        );
+    }
+
+    #[test]
+    fn partial_heating_table_keeps_heating_defaults() {
+        let config = from_toml("[control.heating]\nmin_on = 4").unwrap();
+        assert_eq!(config.control.heating.min_on, Duration::from_secs(4));
+        assert_eq!(
+            config.control.heating.window,
+            OutputConfig::default().window
+        );
+    }
+
+    #[test]
+    fn all_problems_are_reported() {
+        let error = from_toml(
+            "[sensor]\nmin_temp = 50.0\nmax_temp = 10.0\n[control]\ntemp_interval = 0\n[timelapse]\nfps = 0",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("sensor.min_temp must be below sensor.max_temp"));
+        assert!(error.contains("control.temp_interval must be greater than 0"));
+        assert!(error.contains("timelapse.fps must be between 1 and 100"));
+    }
+
+    #[test]
+    fn outputs_need_room_for_min_on_and_min_off() {
+        let error = from_toml("[control.heating]\nwindow = 3")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("control.heating.min_on and min_off must fit into the window"));
+    }
+
+    #[test]
+    fn redacted_hides_the_influx_token() {
+        let config =
+            from_toml("[influx]\nurl = \"http://localhost:8086\"\ntoken = \"secret\"").unwrap();
+        assert_eq!(config.redacted().influx.unwrap().token, "***");
+    }
+
+    #[test]
+    fn redacted_hides_the_admin_token() {
+        let config = from_toml("[admin]\ntoken = \"secret\"").unwrap();
+        assert_eq!(config.redacted().admin.token.as_deref(), Some("***"));
+        assert_eq!(config.admin.max_upload_mb, 1024);
+    }
+}
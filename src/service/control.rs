@@ -0,0 +1,62 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{
+    database::project::{add_event, get_active_project, EventKind},
+    door,
+};
+
+/* while paused the control loops keep running but demand nothing from the actuators */
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ControlStatus {
+    pub paused: bool,
+    /* outputs are suspended while the door is open, independent of pausing */
+    pub door_open: bool,
+}
+
+pub fn status() -> ControlStatus {
+    ControlStatus {
+        paused: is_paused(),
+        door_open: door::is_open(),
+    }
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+pub fn pause() -> Result<ControlStatus, Box<dyn std::error::Error>> {
+    set_paused(true)
+}
+
+pub fn resume() -> Result<ControlStatus, Box<dyn std::error::Error>> {
+    set_paused(false)
+}
+
+pub fn toggle_pause() -> Result<ControlStatus, Box<dyn std::error::Error>> {
+    set_paused(!is_paused())
+}
+
+/* only an active project can be paused, the change is logged as project event */
+fn set_paused(paused: bool) -> Result<ControlStatus, Box<dyn std::error::Error>> {
+    let project = match get_active_project() {
+        Ok(project) => project,
+        Err(_) if !paused => {
+            PAUSED.store(false, Ordering::SeqCst);
+            return Ok(status());
+        }
+        Err(e) => return Err(e),
+    };
+    if PAUSED.swap(paused, Ordering::SeqCst) != paused {
+        let kind = if paused {
+            EventKind::Paused
+        } else {
+            EventKind::Resumed
+        };
+        info!("control of project {} {:?}", project.id, kind);
+        add_event(project.id, kind)?;
+    }
+    Ok(status())
+}
@@ -7,6 +7,8 @@ pub mod project {
 
     use serde::{Deserialize, Serialize};
 
+    use crate::service::config;
+
     #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
     pub struct Project {
         pub id: u32,
@@ -16,6 +18,34 @@ pub mod project {
         pub start_at: Option<u64>,
         pub endend_at: Option<u64>,
         pub settings: Settings,
+        #[serde(default)]
+        pub settings_history: Vec<SettingsChange>,
+        #[serde(default)]
+        pub keep: bool,
+        #[serde(default)]
+        pub events: Vec<ProjectEvent>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum EventKind {
+        Checked,
+        Paused,
+        Resumed,
+        DoorOpened,
+        DoorClosed,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct ProjectEvent {
+        pub time: u64,
+        pub kind: EventKind,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct SettingsChange {
+        pub time: u64,
+        pub settings: Settings,
     }
 
     #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -24,9 +54,9 @@ pub mod project {
         pub temp: f32,
     }
 
-    /* read project json path: db/projects.json */
+    /* read project json path: <db>/projects.json */
     pub fn read_projects() -> Result<Vec<Project>, Box<dyn std::error::Error>> {
-        let mut file = File::open("./db/projects.json")?;
+        let mut file = File::open(config::get().paths.projects_file())?;
         let mut data = String::new();
         file.read_to_string(&mut data)?;
         let projects: Vec<Project> = serde_json::from_str(&data)?;
@@ -58,10 +88,12 @@ pub mod project {
         id: u32,
         name: Option<String>,
         description: Option<String>,
+        keep: Option<bool>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         write_project(Some(id), |project| {
             project.name = name.clone().unwrap_or(project.name.clone());
             project.description = description.clone().unwrap_or(project.description.clone());
+            project.keep = keep.unwrap_or(project.keep);
 
             Ok(())
         })
@@ -74,7 +106,7 @@ pub mod project {
             None => return Err(Box::from("Project not found")),
         };
         projects.remove(index);
-        let mut file = File::create("./db/projects.json")?;
+        let mut file = File::create(config::get().paths.projects_file())?;
         file.write_all(serde_json::to_string(&projects)?.as_bytes())?;
         Ok(())
     }
@@ -102,6 +134,16 @@ pub mod project {
         })
     }
 
+    pub fn add_event(id: u32, kind: EventKind) -> Result<(), Box<dyn std::error::Error>> {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        write_project(Some(id), |project| {
+            project.events.push(ProjectEvent { time, kind });
+            Ok(())
+        })
+    }
+
     pub fn get_project(id: u32) -> Result<Project, Box<dyn std::error::Error>> {
         let projects = read_projects()?;
         match projects.iter().find(|&p| p.id == id) {
@@ -115,11 +157,20 @@ pub mod project {
         settings: Settings,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let mut projects = read_projects()?;
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
         match projects.iter_mut().find(|p| p.id == id) {
-            Some(project) => project.settings = settings,
+            Some(project) => {
+                project.settings_history.push(SettingsChange {
+                    time,
+                    settings: settings.clone(),
+                });
+                project.settings = settings;
+            }
             None => return Err(Box::from("Project not found")),
         };
-        let mut file = File::create("./db/projects.json")?;
+        let mut file = File::create(config::get().paths.projects_file())?;
         file.write_all(serde_json::to_string(&projects)?.as_bytes())?;
         Ok(())
     }
@@ -144,7 +195,7 @@ pub mod project {
             Some(id) => mut_existing_project(&mut projects, id, &f)?,
             None => mut_new_project(&mut projects, f)?,
         };
-        let mut file = File::create("./db/projects.json")?;
+        let mut file = File::create(config::get().paths.projects_file())?;
         file.write_all(serde_json::to_string(&projects)?.as_bytes())?;
         Ok(())
     }
@@ -168,7 +219,14 @@ pub mod project {
                 hum: 75.0,
                 temp: 30.0,
             },
+            settings_history: Vec::new(),
+            keep: false,
+            events: Vec::new(),
         };
+        project.settings_history.push(SettingsChange {
+            time: created_at,
+            settings: project.settings.clone(),
+        });
         f(&mut project)?;
         projects.push(project.clone());
         Ok(())
@@ -190,12 +248,13 @@ pub mod sensor {
     use std::{
         fs::File,
         io::{Read, Write},
-        path::Path,
     };
 
     use ::serde::{Serialize, Serializer};
     use serde::Deserialize;
 
+    use crate::service::config;
+
     #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
     #[serde(crate = "rocket::serde")]
     pub struct SensorData {
@@ -210,6 +269,13 @@ pub mod sensor {
     pub struct HistoricSensorData {
         pub time: u64,
         pub data: SensorData,
+        /* recorded while the chamber door was open, only written when set */
+        #[serde(default, skip_serializing_if = "is_false")]
+        pub door_open: bool,
+    }
+
+    fn is_false(value: &bool) -> bool {
+        !*value
     }
 
     fn round_serialize<S>(x: &f32, s: S) -> Result<S::Ok, S::Error>
@@ -227,7 +293,7 @@ pub mod sensor {
 
         match date {
             Some(date) => {
-                let path = format!("./db/sensor/{}.json", date.format("%Y-%m-%d"));
+                let path = config::get().paths.sensor_file(date.date());
                 let mut file = File::open(path)?;
                 let mut data = String::new();
                 file.read_to_string(&mut data)?;
@@ -239,10 +305,25 @@ pub mod sensor {
         Ok(historic_sensor_data)
     }
 
+    /* like get_all_data, but a missing day file is just a day without readings */
+    pub fn read_day(time: u64) -> Result<Vec<HistoricSensorData>, Box<dyn std::error::Error>> {
+        let date = match chrono::DateTime::from_timestamp(time as i64, 0) {
+            Some(date) => date.date_naive(),
+            None => return Ok(Vec::new()),
+        };
+        let path = config::get().paths.sensor_file(date);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut data = String::new();
+        File::open(path)?.read_to_string(&mut data)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
     pub fn add_datapoint(data: HistoricSensorData) -> Result<(), Box<dyn std::error::Error>> {
         match chrono::NaiveDateTime::from_timestamp_opt(data.time as i64, 0) {
             Some(date) => {
-                let path = format!("./db/sensor/{}.json", date.format("%Y-%m-%d"));
+                let path = config::get().paths.sensor_file(date.date());
                 let mut historic_data = get_all_data(data.time)?;
                 historic_data.push(data);
                 let mut file = File::create(path)?;
@@ -253,11 +334,22 @@ pub mod sensor {
         Ok(())
     }
 
+    /* one timestamp per day file touched by the range, starting at midnight UTC */
+    pub fn days_between(start_ticks: u64, end_ticks: u64) -> Vec<u64> {
+        let mut days = Vec::new();
+        let mut day = start_ticks - start_ticks % 86400;
+        while day <= end_ticks {
+            days.push(day);
+            day += 86400;
+        }
+        days
+    }
+
     fn create_new_sensor_page(time: u64) -> Result<(), Box<dyn std::error::Error>> {
         match chrono::NaiveDateTime::from_timestamp_opt(time as i64, 0) {
             Some(date) => {
-                let path = format!("./db/sensor/{}.json", date.format("%Y-%m-%d"));
-                if Path::new(&path).exists() {
+                let path = config::get().paths.sensor_file(date.date());
+                if path.exists() {
                     return Ok(());
                 }
                 let mut file = File::create(path)?;
@@ -267,4 +359,28 @@ pub mod sensor {
         }
         Ok(())
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn days_between_covers_partial_days() {
+            assert_eq!(
+                days_between(86400 + 3600, 3 * 86400 + 10),
+                vec![86400, 2 * 86400, 3 * 86400]
+            );
+            assert_eq!(days_between(100, 200), vec![0]);
+        }
+
+        #[test]
+        fn read_day_does_not_create_missing_files() {
+            let time = 4102444800; /* 2100-01-01 */
+            let path = config::get()
+                .paths
+                .sensor_file(chrono::NaiveDate::from_ymd_opt(2100, 1, 1).unwrap());
+            assert_eq!(read_day(time).unwrap(), Vec::new());
+            assert!(!path.exists());
+        }
+    }
 }
@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::database::project::{add_event, get_active_project, EventKind};
+
+/* closed until the door worker reads the reed switch, chambers without one never open */
+static DOOR_OPEN: AtomicBool = AtomicBool::new(false);
+
+pub fn is_open() -> bool {
+    DOOR_OPEN.load(Ordering::SeqCst)
+}
+
+/* record a debounced change of the reed switch, logged as event of the active project */
+pub fn set_open(open: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if DOOR_OPEN.swap(open, Ordering::SeqCst) == open {
+        return Ok(());
+    }
+    info!("door {}", if open { "opened" } else { "closed" });
+    match get_active_project() {
+        Ok(project) => add_event(
+            project.id,
+            if open {
+                EventKind::DoorOpened
+            } else {
+                EventKind::DoorClosed
+            },
+        ),
+        Err(_) => Ok(()),
+    }
+}
@@ -0,0 +1,197 @@
+use parquet::{
+    basic::Compression,
+    data_type::{BoolType, FloatType, Int64Type},
+    file::{metadata::KeyValue, properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use std::{fs::File, path::Path, sync::Arc};
+
+use super::database::{
+    project::{Project, Settings},
+    sensor::{days_between, read_day, HistoricSensorData},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, FromFormField)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+const PARQUET_SCHEMA: &str = "
+    message reading {
+        REQUIRED INT64 time (TIMESTAMP(MILLIS,true));
+        REQUIRED FLOAT temp;
+        REQUIRED FLOAT hum;
+        REQUIRED FLOAT target_temp;
+        REQUIRED FLOAT target_hum;
+        REQUIRED BOOLEAN door_open;
+    }
+";
+
+/* time range the project was running, an unfinished project runs until now */
+pub fn project_range(project: &Project) -> Option<(u64, u64)> {
+    let start = project.start_at?;
+    let end = match project.endend_at {
+        Some(end) => end,
+        None => chrono::Utc::now().timestamp() as u64,
+    };
+    Some((start, end))
+}
+
+pub fn project_days(project: &Project) -> Vec<u64> {
+    match project_range(project) {
+        Some((start, end)) => days_between(start, end),
+        None => Vec::new(),
+    }
+}
+
+/* readings of a single day file that belong to the project */
+pub fn day_readings(
+    project: &Project,
+    day: u64,
+) -> Result<Vec<HistoricSensorData>, Box<dyn std::error::Error>> {
+    let (start, end) = match project_range(project) {
+        Some(range) => range,
+        None => return Ok(Vec::new()),
+    };
+    Ok(read_day(day)?
+        .into_iter()
+        .filter(|data| data.time >= start && data.time <= end)
+        .collect())
+}
+
+pub fn settings_at(project: &Project, time: u64) -> Settings {
+    match project
+        .settings_history
+        .iter()
+        .rev()
+        .find(|change| change.time <= time)
+    {
+        Some(change) => change.settings.clone(),
+        None => match project.settings_history.first() {
+            Some(change) => change.settings.clone(),
+            None => project.settings.clone(),
+        },
+    }
+}
+
+pub fn json_head(project: &Project) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(format!(
+        "{{\"project\":{},\"readings\":[",
+        serde_json::to_string(project)?
+    ))
+}
+
+pub fn json_readings(
+    readings: &[HistoricSensorData],
+    first: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut out = String::new();
+    for (index, reading) in readings.iter().enumerate() {
+        if !first || index > 0 {
+            out.push(',');
+        }
+        out.push_str(&serde_json::to_string(reading)?);
+    }
+    Ok(out)
+}
+
+pub fn json_tail() -> String {
+    String::from("]}")
+}
+
+/* project metadata as comment lines, readable with pandas `comment="#"` */
+pub fn csv_head(project: &Project) -> Result<String, Box<dyn std::error::Error>> {
+    let mut out = String::new();
+    out.push_str(&format!("# project: {}\n", serde_json::to_string(project)?));
+    out.push_str("time,temp,hum,target_temp,target_hum,door_open\n");
+    Ok(out)
+}
+
+pub fn csv_rows(project: &Project, readings: &[HistoricSensorData]) -> String {
+    let mut out = String::new();
+    for reading in readings {
+        let settings = settings_at(project, reading.time);
+        out.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            reading.time,
+            reading.data.temp,
+            reading.data.hum,
+            settings.temp,
+            settings.hum,
+            reading.door_open
+        ));
+    }
+    out
+}
+
+/* write one row group per day so only a single day is held in memory */
+pub fn write_parquet(project: &Project, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_key_value_metadata(Some(vec![KeyValue::new(
+                String::from("project"),
+                serde_json::to_string(project)?,
+            )]))
+            .build(),
+    );
+    let mut writer = SerializedFileWriter::new(File::create(path)?, schema, properties)?;
+
+    for day in project_days(project) {
+        let readings = day_readings(project, day)?;
+        if readings.is_empty() {
+            continue;
+        }
+        let settings: Vec<Settings> = readings
+            .iter()
+            .map(|reading| settings_at(project, reading.time))
+            .collect();
+        let times: Vec<i64> = readings
+            .iter()
+            .map(|reading| reading.time as i64 * 1000)
+            .collect();
+        let floats: [Vec<f32>; 4] = [
+            readings.iter().map(|reading| reading.data.temp).collect(),
+            readings.iter().map(|reading| reading.data.hum).collect(),
+            settings.iter().map(|settings| settings.temp).collect(),
+            settings.iter().map(|settings| settings.hum).collect(),
+        ];
+        let door_open: Vec<bool> = readings.iter().map(|reading| reading.door_open).collect();
+
+        let mut row_group = writer.next_row_group()?;
+        let mut column_index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            if column_index == 0 {
+                column
+                    .typed::<Int64Type>()
+                    .write_batch(&times, None, None)?;
+            } else if column_index == floats.len() + 1 {
+                column
+                    .typed::<BoolType>()
+                    .write_batch(&door_open, None, None)?;
+            } else {
+                column
+                    .typed::<FloatType>()
+                    .write_batch(&floats[column_index - 1], None, None)?;
+            }
+            column.close()?;
+            column_index += 1;
+        }
+        row_group.close()?;
+    }
+    writer.close()?;
+    Ok(())
+}
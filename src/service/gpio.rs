@@ -1,61 +1,318 @@
 use lazy_static::lazy_static;
+use serde::{de, Deserialize, Deserializer, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     hash::Hash,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
-use rppal::gpio::{Gpio, IoPin, Mode};
+use rppal::{
+    gpio::{Bias, Gpio, InputPin, IoPin, Level, Mode, Trigger},
+    pwm::{Channel, Polarity, Pwm},
+};
 use std::thread;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
-#[repr(u8)]
-enum PinType {
-    HeatingPin = 17,
-    HumidifierPin = 4,
-    Led3Pin = 27,
-    Led1Pin = 22,
-    Led2Pin = 10,
-    SensorPin = 2,
-    Sensor2Pin = 3,
+use super::{
+    config::{self, seconds},
+    metrics::{self, Counter},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinRole {
+    Heating,
+    Cooling,
+    Humidifier,
+    Fan,
+    Led1,
+    Led2,
+    Led3,
+    Sensor,
+    Sensor2,
+    /* reed switch input, "active" means the door is open */
+    Door,
+}
+
+/* relays are switched by time-proportioning, MOSFETs and SSRs can take the duty as PWM */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    #[default]
+    Relay,
+    Pwm,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PinConfig {
+    /* BCM pin number */
+    pub pin: u8,
+    /* relay boards switch on when the pin is pulled low */
+    #[serde(default = "default_active_low")]
+    pub active_low: bool,
+    #[serde(default)]
+    pub mode: OutputMode,
+    /* hardware PWM on GPIO 12/13/18/19, software PWM on any other pin */
+    #[serde(default = "default_pwm_frequency")]
+    pub pwm_frequency: f64,
+}
+
+/* push-button between the pin and ground (active low, internal pull-up) or 3.3 V (pull-down) */
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ButtonConfig {
+    /* BCM pin number */
+    pub pin: u8,
+    #[serde(default = "default_active_low")]
+    pub active_low: bool,
+    /* a level counts once it stayed the same this long, shorter ones are contact bounce */
+    #[serde(default = "default_debounce", with = "seconds")]
+    pub debounce: Duration,
+    /* presses held at least this long are long presses */
+    #[serde(default = "default_long_press", with = "seconds")]
+    pub long_press: Duration,
+    /* a second press within this time after releasing makes a double press */
+    #[serde(default = "default_double_press", with = "seconds")]
+    pub double_press: Duration,
+}
+
+/* a [gpio] section replaces the whole stock mapping, roles left out are not used */
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GpioConfig {
+    #[serde(deserialize_with = "pins_with_role_defaults")]
+    pub pins: HashMap<PinRole, PinConfig>,
+    #[serde(default)]
+    pub buttons: Vec<ButtonConfig>,
+}
+
+impl Default for GpioConfig {
+    fn default() -> Self {
+        let mut pins = HashMap::new();
+        pins.insert(PinRole::Heating, PinConfig::active_low(17));
+        pins.insert(PinRole::Humidifier, PinConfig::active_low(4));
+        pins.insert(PinRole::Led3, PinConfig::active_low(27));
+        pins.insert(PinRole::Led1, PinConfig::active_low(22));
+        pins.insert(PinRole::Led2, PinConfig::active_low(10));
+        pins.insert(PinRole::Sensor, PinConfig::active_low(2));
+        pins.insert(PinRole::Sensor2, PinConfig::active_low(3));
+        GpioConfig {
+            pins,
+            buttons: Vec::new(),
+        }
+    }
+}
+
+impl PinConfig {
+    fn active_low(pin: u8) -> Self {
+        PinConfig {
+            pin,
+            active_low: true,
+            mode: OutputMode::Relay,
+            pwm_frequency: default_pwm_frequency(),
+        }
+    }
+}
+
+fn default_active_low() -> bool {
+    true
+}
+
+/* relay boards are active low, a door reed switch to ground reads high once the door opens */
+fn default_active_low_of(role: PinRole) -> bool {
+    role != PinRole::Door
+}
+
+fn pins_with_role_defaults<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<PinRole, PinConfig>, D::Error> {
+    let pins: HashMap<PinRole, serde_json::Map<String, serde_json::Value>> =
+        HashMap::deserialize(deserializer)?;
+    pins.into_iter()
+        .map(|(role, mut fields)| {
+            fields
+                .entry("active_low")
+                .or_insert(serde_json::Value::Bool(default_active_low_of(role)));
+            serde_json::from_value(serde_json::Value::Object(fields))
+                .map(|pin_config| (role, pin_config))
+                .map_err(de::Error::custom)
+        })
+        .collect()
+}
+
+fn default_pwm_frequency() -> f64 {
+    100.0
+}
+
+fn default_debounce() -> Duration {
+    Duration::from_millis(50)
+}
+
+fn default_long_press() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_double_press() -> Duration {
+    Duration::from_millis(400)
 }
 
 const TIMEOUT_DURATION: u128 = 300;
 
+/* set on shutdown, after that actuators can only be switched off */
+static OUTPUTS_DISABLED: AtomicBool = AtomicBool::new(false);
+
 lazy_static! {
-    static ref PINS: HashMap<PinType, Arc<Mutex<IoPin>>> = {
+    static ref HARDWARE_PWM: HashMap<PinRole, Pwm> = {
         let mut m = HashMap::new();
-        m.insert(
-            PinType::HeatingPin,
-            init_pin_mutex(PinType::HeatingPin).unwrap(),
-        );
-        m.insert(
-            PinType::HumidifierPin,
-            init_pin_mutex(PinType::HumidifierPin).unwrap(),
-        );
-        m.insert(PinType::Led3Pin, init_pin_mutex(PinType::Led3Pin).unwrap());
-        m.insert(PinType::Led1Pin, init_pin_mutex(PinType::Led1Pin).unwrap());
-        m.insert(PinType::Led2Pin, init_pin_mutex(PinType::Led2Pin).unwrap());
-        m.insert(
-            PinType::SensorPin,
-            init_pin_mutex(PinType::SensorPin).unwrap(),
-        );
-        m.insert(
-            PinType::Sensor2Pin,
-            init_pin_mutex(PinType::Sensor2Pin).unwrap(),
-        );
+        for (role, pin_config) in get_config().pins.iter() {
+            if pin_config.mode != OutputMode::Pwm {
+                continue;
+            }
+            if let Some(channel) = pwm_channel(pin_config.pin) {
+                match init_pwm(channel, pin_config) {
+                    Ok(pwm) => {
+                        m.insert(*role, pwm);
+                    }
+                    Err(e) => {
+                        error!(
+                            "Error: {:?} on pin {}: {}, falling back to software PWM",
+                            role, pin_config.pin, e
+                        );
+                    }
+                }
+            }
+        }
+        m
+    };
+    static ref PINS: HashMap<PinRole, Arc<Mutex<IoPin>>> = {
+        let mut m = HashMap::new();
+        for (role, pin_config) in get_config().pins.iter() {
+            if HARDWARE_PWM.contains_key(role) {
+                continue;
+            }
+            match init_pin_mutex(pin_config.pin) {
+                Ok(pin) => {
+                    if *role == PinRole::Door {
+                        set_input(&pin, pin_config);
+                    } else if *role != PinRole::Sensor && *role != PinRole::Sensor2 {
+                        set_level(&pin, pin_config, false);
+                    }
+                    m.insert(*role, pin);
+                }
+                Err(e) => {
+                    error!("Error: {:?} on pin {}: {}", role, pin_config.pin, e);
+                }
+            }
+        }
         m
     };
 }
 
+pub fn validate_config(config: &GpioConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let mut used = HashSet::new();
+    for (role, pin_config) in config.pins.iter() {
+        if !used.insert(pin_config.pin) {
+            return Err(Box::from(format!(
+                "Pin {} is assigned more than once (again for {:?})",
+                pin_config.pin, role
+            )));
+        }
+    }
+    for button in config.buttons.iter() {
+        if !used.insert(button.pin) {
+            return Err(Box::from(format!(
+                "Pin {} is assigned more than once (again for a button)",
+                button.pin
+            )));
+        }
+    }
+    /* PWM bypasses min_on, min_off and the switch budget, which a compressor relies on */
+    for role in [
+        PinRole::Sensor,
+        PinRole::Sensor2,
+        PinRole::Door,
+        PinRole::Cooling,
+    ] {
+        if let Some(pin_config) = config.pins.get(&role) {
+            if pin_config.mode == OutputMode::Pwm {
+                return Err(Box::from(format!("{:?} can not be a PWM output", role)));
+            }
+        }
+    }
+    for (role, pin_config) in config.pins.iter() {
+        if pin_config.mode == OutputMode::Pwm && pin_config.pwm_frequency <= 0.0 {
+            return Err(Box::from(format!(
+                "PWM frequency of {:?} must be greater than 0",
+                role
+            )));
+        }
+    }
+    /* the two pins of a hardware channel share its duty cycle */
+    let mut channels: Vec<(Channel, PinRole)> = Vec::new();
+    for (role, pin_config) in config.pins.iter() {
+        if pin_config.mode != OutputMode::Pwm {
+            continue;
+        }
+        if let Some(channel) = pwm_channel(pin_config.pin) {
+            if let Some((_, other)) = channels.iter().find(|(used, _)| *used == channel) {
+                return Err(Box::from(format!(
+                    "{:?} and {:?} use the same hardware PWM channel {:?}",
+                    other, role, channel
+                )));
+            }
+            channels.push((channel, *role));
+        }
+    }
+    if !config.pins.contains_key(&PinRole::Sensor) {
+        return Err(Box::from("No sensor pin configured"));
+    }
+    Ok(())
+}
+
+pub fn get_config() -> &'static GpioConfig {
+    &config::get().gpio
+}
+
+pub fn has_role(role: PinRole) -> bool {
+    get_config().pins.contains_key(&role)
+}
+
+pub fn output_mode(role: PinRole) -> OutputMode {
+    match get_config().pins.get(&role) {
+        Some(pin_config) => pin_config.mode,
+        None => OutputMode::Relay,
+    }
+}
+
+/* calls back with true on press and false on release, the interrupt stops when the pin is dropped */
+pub fn watch_button(
+    button: &ButtonConfig,
+    mut callback: impl FnMut(bool) + Send + 'static,
+) -> Result<InputPin, Box<dyn std::error::Error>> {
+    let pin = Gpio::new()?.get(button.pin)?;
+    let mut input = if button.active_low {
+        pin.into_input_pullup()
+    } else {
+        pin.into_input_pulldown()
+    };
+    let active_low = button.active_low;
+    input.set_async_interrupt(Trigger::Both, move |level| {
+        callback((level == Level::Low) == active_low)
+    })?;
+    Ok(input)
+}
+
 pub fn read_sensor_data() -> Result<(f32, f32), Box<dyn std::error::Error>> {
     let mut array: [u8; 5] = [0; 5];
-    let mut pin_lock = get_pin_save(PinType::SensorPin)?;
+    let mut pin_lock = get_pin_save(PinRole::Sensor)?;
     pin_lock.set_mode(rppal::gpio::Mode::Output);
+    metrics::inc(Counter::SensorReadAttempts);
     match read_sensor_from_pin(&mut pin_lock, &mut array) {
         Ok(_) => {}
         Err(e) => {
             error!("Error: {}", e);
+            metrics::inc(Counter::SensorReadFailures);
             return Err(e);
         }
     }
@@ -67,9 +324,9 @@ pub fn read_sensor_data() -> Result<(f32, f32), Box<dyn std::error::Error>> {
 }
 
 fn get_pin_save(
-    pin_number: PinType,
+    role: PinRole,
 ) -> Result<std::sync::MutexGuard<'static, IoPin>, Box<dyn std::error::Error>> {
-    let pin_lock = match PINS.get(&pin_number) {
+    let pin_lock = match PINS.get(&role) {
         Some(pin) => match pin.lock() {
             Ok(pin) => pin,
             Err(e) => {
@@ -93,61 +350,209 @@ fn read_sensor_from_pin(
     Ok(())
 }
 
-pub fn turn_off_heating() -> Result<(), Box<dyn std::error::Error>> {
-    let mut pin_lock = get_pin_save(PinType::HeatingPin)?;
+/* drive an output role to its on or off level according to its polarity */
+fn set_output(role: PinRole, on: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let pin_config = match get_config().pins.get(&role) {
+        Some(pin_config) => pin_config,
+        None => return Err(Box::from("Pin not found")),
+    };
+    if pin_config.mode == OutputMode::Pwm {
+        return set_pwm(role, pin_config, if on { 1.0 } else { 0.0 });
+    }
+    let active_low = pin_config.active_low;
+    let mut pin_lock = get_pin_save(role)?;
 
-    pin_lock.set_mode(rppal::gpio::Mode::Output);
-    pin_lock.set_high();
+    pin_lock.set_mode(Mode::Output);
+    if on == active_low {
+        pin_lock.set_low();
+    } else {
+        pin_lock.set_high();
+    }
     Ok(())
 }
 
-pub fn turn_on_heating() -> Result<(), Box<dyn std::error::Error>> {
-    let mut pin_lock = get_pin_save(PinType::HeatingPin)?;
+/* duty between 0 and 1 for a role in PWM mode, inverted for active low wiring */
+pub fn set_output_duty(role: PinRole, duty: f32) -> Result<(), Box<dyn std::error::Error>> {
+    let pin_config = match get_config().pins.get(&role) {
+        Some(pin_config) => pin_config,
+        None => return Err(Box::from("Pin not found")),
+    };
+    if pin_config.mode != OutputMode::Pwm {
+        return Err(Box::from("Pin not in PWM mode"));
+    }
+    if duty > 0.0 {
+        check_outputs_enabled()?;
+    }
+    set_pwm(role, pin_config, duty.clamp(0.0, 1.0) as f64)
+}
 
+fn set_pwm(
+    role: PinRole,
+    pin_config: &PinConfig,
+    duty: f64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let level = if pin_config.active_low {
+        1.0 - duty
+    } else {
+        duty
+    };
+    if let Some(pwm) = HARDWARE_PWM.get(&role) {
+        pwm.set_duty_cycle(level)?;
+        return Ok(());
+    }
+    let mut pin_lock = get_pin_save(role)?;
     pin_lock.set_mode(Mode::Output);
-    pin_lock.set_low();
+    /* software PWM keeps a thread toggling the pin, plain levels are cheaper at the ends */
+    if level <= 0.0 || level >= 1.0 {
+        pin_lock.clear_pwm()?;
+        if level >= 1.0 {
+            pin_lock.set_high();
+        } else {
+            pin_lock.set_low();
+        }
+    } else {
+        pin_lock.set_pwm_frequency(pin_config.pwm_frequency, level)?;
+    }
     Ok(())
 }
 
-pub fn turn_off_humidifier() -> Result<(), Box<dyn std::error::Error>> {
-    let mut pin_lock = get_pin_save(PinType::HumidifierPin)?;
+fn pwm_channel(pin: u8) -> Option<Channel> {
+    match pin {
+        12 | 18 => Some(Channel::Pwm0),
+        13 | 19 => Some(Channel::Pwm1),
+        _ => None,
+    }
+}
 
-    pin_lock.set_mode(Mode::Output);
-    pin_lock.set_high();
-    Ok(())
+/* needs the pwm-2chan overlay, the channel starts at the off level */
+fn init_pwm(channel: Channel, pin_config: &PinConfig) -> Result<Pwm, Box<dyn std::error::Error>> {
+    let off = if pin_config.active_low { 1.0 } else { 0.0 };
+    Ok(Pwm::with_frequency(
+        channel,
+        pin_config.pwm_frequency,
+        off,
+        Polarity::Normal,
+        true,
+    )?)
+}
+
+/* the reed switch is open while the door is, so the bias sets the open level: a switch to
+ground (active_low = false) needs the pull-up, a switch to 3.3 V the pull-down */
+fn set_input(pin: &Arc<Mutex<IoPin>>, pin_config: &PinConfig) {
+    if let Ok(mut pin) = pin.lock() {
+        pin.set_mode(Mode::Input);
+        pin.set_bias(if pin_config.active_low {
+            Bias::PullDown
+        } else {
+            Bias::PullUp
+        });
+    }
+}
+
+pub fn read_door() -> Result<bool, Box<dyn std::error::Error>> {
+    let active_low = match get_config().pins.get(&PinRole::Door) {
+        Some(pin_config) => pin_config.active_low,
+        None => return Err(Box::from("Pin not found")),
+    };
+    let pin_lock = get_pin_save(PinRole::Door)?;
+    Ok(pin_lock.is_low() == active_low)
+}
+
+/* outputs start in their off level instead of whatever into_io left them in */
+fn set_level(pin: &Arc<Mutex<IoPin>>, pin_config: &PinConfig, on: bool) {
+    if let Ok(mut pin) = pin.lock() {
+        pin.set_mode(Mode::Output);
+        if on == pin_config.active_low {
+            pin.set_low();
+        } else {
+            pin.set_high();
+        }
+    }
+}
+
+pub fn turn_off_heating() -> Result<(), Box<dyn std::error::Error>> {
+    set_output(PinRole::Heating, false)
+}
+
+pub fn turn_on_heating() -> Result<(), Box<dyn std::error::Error>> {
+    check_outputs_enabled()?;
+    set_output(PinRole::Heating, true)
+}
+
+pub fn turn_off_cooling() -> Result<(), Box<dyn std::error::Error>> {
+    set_output(PinRole::Cooling, false)
+}
+
+pub fn turn_on_cooling() -> Result<(), Box<dyn std::error::Error>> {
+    check_outputs_enabled()?;
+    set_output(PinRole::Cooling, true)
+}
+
+pub fn turn_off_humidifier() -> Result<(), Box<dyn std::error::Error>> {
+    set_output(PinRole::Humidifier, false)
 }
 
 pub fn turn_on_humidifier() -> Result<(), Box<dyn std::error::Error>> {
-    let mut pin_lock = get_pin_save(PinType::HumidifierPin)?;
+    check_outputs_enabled()?;
+    set_output(PinRole::Humidifier, true)
+}
 
-    pin_lock.set_mode(Mode::Output);
-    pin_lock.set_low();
+/* drive all actuators to their off level and refuse to switch them on again; one failing
+actuator must not keep the others running, so every one is tried before reporting errors */
+pub fn disable_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    OUTPUTS_DISABLED.store(true, Ordering::SeqCst);
+    let mut errors = Vec::new();
+    for role in [
+        PinRole::Heating,
+        PinRole::Cooling,
+        PinRole::Humidifier,
+        PinRole::Fan,
+    ] {
+        if has_role(role) {
+            if let Err(e) = set_output(role, false) {
+                error!("Error: switching off {:?}: {}", role, e);
+                errors.push(format!("{:?}: {}", role, e));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Box::from(errors.join(", ")))
+    }
+}
+
+fn check_outputs_enabled() -> Result<(), Box<dyn std::error::Error>> {
+    if OUTPUTS_DISABLED.load(Ordering::SeqCst) {
+        return Err(Box::from("Outputs disabled"));
+    }
     Ok(())
 }
 
+pub fn turn_off_fan() -> Result<(), Box<dyn std::error::Error>> {
+    set_output(PinRole::Fan, false)
+}
+
+pub fn turn_on_fan() -> Result<(), Box<dyn std::error::Error>> {
+    check_outputs_enabled()?;
+    set_output(PinRole::Fan, true)
+}
+
 pub fn turn_off_led(led_index: u8) -> Result<(), Box<dyn std::error::Error>> {
-    let mut pin_lock = match led_index {
-        1 => get_pin_save(PinType::Led1Pin)?,
-        2 => get_pin_save(PinType::Led2Pin)?,
-        3 => get_pin_save(PinType::Led3Pin)?,
-        _ => return Err(Box::from("Index")),
-    };
-    pin_lock.set_mode(Mode::Output);
-    pin_lock.set_high();
-    Ok(())
+    set_output(led_role(led_index)?, false)
 }
 
 pub fn turn_on_led(led_index: u8) -> Result<(), Box<dyn std::error::Error>> {
-    let mut pin_lock = match led_index {
-        1 => get_pin_save(PinType::Led1Pin)?,
-        2 => get_pin_save(PinType::Led2Pin)?,
-        3 => get_pin_save(PinType::Led3Pin)?,
-        _ => return Err(Box::from("Index")),
-    };
-    pin_lock.set_mode(Mode::Output);
-    pin_lock.set_low();
+    set_output(led_role(led_index)?, true)
+}
 
-    Ok(())
+fn led_role(led_index: u8) -> Result<PinRole, Box<dyn std::error::Error>> {
+    match led_index {
+        1 => Ok(PinRole::Led1),
+        2 => Ok(PinRole::Led2),
+        3 => Ok(PinRole::Led3),
+        _ => Err(Box::from("Index")),
+    }
 }
 
 fn start_signal(pin: &mut IoPin) -> Result<(), Box<dyn std::error::Error>> {
@@ -169,6 +574,7 @@ fn wait_for_low(pin: &IoPin) -> Result<(), Box<dyn std::error::Error>> {
     let timeout_start = std::time::Instant::now();
     while pin.is_high() {
         if timeout_start.elapsed().as_millis() > TIMEOUT_DURATION {
+            metrics::inc(Counter::SensorTimeouts);
             return Err(Box::from("Timeout"));
         }
     }
@@ -179,6 +585,7 @@ fn wait_for_high(pin: &IoPin) -> Result<(), Box<dyn std::error::Error>> {
     let timeout_start = std::time::Instant::now();
     while pin.is_low() {
         if timeout_start.elapsed().as_millis() > TIMEOUT_DURATION {
+            metrics::inc(Counter::SensorTimeouts);
             return Err(Box::from("Timeout"));
         }
     }
@@ -193,6 +600,7 @@ fn read_byte(pin: &IoPin) -> Result<u8, Box<dyn std::error::Error>> {
         timeout = std::time::Instant::now();
         while pin.is_low() {
             if timeout.elapsed().as_millis() > TIMEOUT_DURATION {
+                metrics::inc(Counter::SensorTimeouts);
                 return Err(Box::from("Timeout"));
             }
         }
@@ -200,6 +608,7 @@ fn read_byte(pin: &IoPin) -> Result<u8, Box<dyn std::error::Error>> {
         high_time = std::time::Instant::now();
         while pin.is_high() {
             if timeout.elapsed().as_millis() > TIMEOUT_DURATION {
+                metrics::inc(Counter::SensorTimeouts);
                 return Err(Box::from("Timeout"));
             }
         }
@@ -210,9 +619,9 @@ fn read_byte(pin: &IoPin) -> Result<u8, Box<dyn std::error::Error>> {
     Ok(value)
 }
 
-fn init_pin_mutex(pin: PinType) -> Result<Arc<Mutex<IoPin>>, Box<dyn std::error::Error>> {
+fn init_pin_mutex(pin: u8) -> Result<Arc<Mutex<IoPin>>, Box<dyn std::error::Error>> {
     match Gpio::new() {
-        Ok(gpio) => match gpio.get(pin as u8) {
+        Ok(gpio) => match gpio.get(pin) {
             Ok(pin) => return Ok(Arc::new(Mutex::new(pin.into_io(Mode::Output)))),
             Err(e) => {
                 error!("Error: {}", e);
@@ -244,7 +653,80 @@ fn read_data(array: &mut [u8; 5], pin: &IoPin) -> Result<(), Box<dyn std::error:
             .wrapping_add(array[3])
             & 0xFF)
     {
+        metrics::inc(Counter::SensorChecksumErrors);
         return Err(Box::from("Checksum"));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pwm(pin: u8) -> PinConfig {
+        PinConfig {
+            mode: OutputMode::Pwm,
+            ..PinConfig::active_low(pin)
+        }
+    }
+
+    #[test]
+    fn cooling_can_not_be_pwm() {
+        let mut config = GpioConfig::default();
+        config.pins.insert(PinRole::Fan, pwm(18));
+        assert!(validate_config(&config).is_ok());
+        config.pins.insert(PinRole::Cooling, pwm(19));
+        assert_eq!(
+            validate_config(&config).unwrap_err().to_string(),
+            "Cooling can not be a PWM output"
+        );
+    }
+
+    #[test]
+    fn pwm_outputs_can_not_share_a_channel() {
+        let mut config = GpioConfig::default();
+        config.pins.insert(PinRole::Heating, pwm(12));
+        config.pins.insert(PinRole::Fan, pwm(19));
+        assert!(validate_config(&config).is_ok());
+        config.pins.insert(PinRole::Fan, pwm(18));
+        let error = validate_config(&config).unwrap_err().to_string();
+        assert!(error.contains("use the same hardware PWM channel Pwm0"));
+        /* a relay on the other pin of the channel is fine */
+        let mut relay = pwm(18);
+        relay.mode = OutputMode::Relay;
+        config.pins.insert(PinRole::Fan, relay);
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn door_defaults_to_active_high() {
+        let config: GpioConfig = serde_json::from_str(
+            r#"{"pins": {"sensor": {"pin": 2}, "heating": {"pin": 17}, "door": {"pin": 6}}}"#,
+        )
+        .unwrap();
+        assert!(!config.pins[&PinRole::Door].active_low);
+        assert!(config.pins[&PinRole::Heating].active_low);
+
+        let config: GpioConfig = serde_json::from_str(
+            r#"{"pins": {"sensor": {"pin": 2}, "door": {"pin": 6, "active_low": true}}}"#,
+        )
+        .unwrap();
+        assert!(config.pins[&PinRole::Door].active_low);
+    }
+
+    #[test]
+    fn pins_can_not_be_shared() {
+        let mut config = GpioConfig::default();
+        config.pins.insert(PinRole::Fan, PinConfig::active_low(17));
+        assert!(validate_config(&config).is_err());
+    }
+
+    /* no GPIO in the test environment, so every configured output fails to switch off */
+    #[test]
+    fn disable_outputs_tries_every_actuator() {
+        let error = disable_outputs().unwrap_err().to_string();
+        assert!(error.contains("Heating"), "{}", error);
+        assert!(error.contains("Humidifier"), "{}", error);
+        assert!(check_outputs_enabled().is_err());
+    }
+}
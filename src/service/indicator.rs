@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::{
+    alarm, analysis,
+    config::{self, seconds},
+    door,
+    output::{self, Actuator},
+    sensor::last_reading_at,
+};
+use crate::basic_runners::supervisor::{get_worker_status, WorkerState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedCondition {
+    /* no fault and a fresh sensor reading */
+    Healthy,
+    /* an alarm nobody acknowledged yet */
+    Alarm,
+    /* a worker is failing and waiting for its restart */
+    Fault,
+    /* no valid sensor reading for longer than stale_sensor_after */
+    StaleSensor,
+    Heating,
+    Cooling,
+    Humidifying,
+    Venting,
+    DoorOpen,
+    /* contamination coloured area grew beyond analysis.contamination_threshold */
+    Contamination,
+    Always,
+}
+
+/* the first rule whose condition holds drives the LED, it stays off when none does */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LedRule {
+    pub when: LedCondition,
+    /* on for `on`, then off for `off`, solid when off is 0 */
+    #[serde(with = "seconds")]
+    pub on: Duration,
+    #[serde(default, with = "seconds")]
+    pub off: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndicatorConfig {
+    #[serde(with = "seconds")]
+    pub stale_sensor_after: Duration,
+    pub led1: Vec<LedRule>,
+    pub led2: Vec<LedRule>,
+    pub led3: Vec<LedRule>,
+}
+
+impl Default for IndicatorConfig {
+    fn default() -> Self {
+        IndicatorConfig {
+            stale_sensor_after: Duration::from_secs(30),
+            led1: vec![LedRule::blink(LedCondition::Healthy, 0.1, 0.9)],
+            led2: vec![
+                LedRule::solid(LedCondition::Heating),
+                LedRule::solid(LedCondition::Cooling),
+                LedRule::blink(LedCondition::Humidifying, 0.5, 0.5),
+            ],
+            led3: vec![
+                LedRule::solid(LedCondition::Alarm),
+                LedRule::blink(LedCondition::Fault, 0.5, 0.5),
+                LedRule::blink(LedCondition::StaleSensor, 0.5, 0.5),
+            ],
+        }
+    }
+}
+
+impl LedRule {
+    fn solid(when: LedCondition) -> Self {
+        LedRule {
+            when,
+            on: Duration::from_secs(1),
+            off: Duration::ZERO,
+        }
+    }
+
+    fn blink(when: LedCondition, on: f64, off: f64) -> Self {
+        LedRule {
+            when,
+            on: Duration::from_secs_f64(on),
+            off: Duration::from_secs_f64(off),
+        }
+    }
+
+    /* level at `elapsed` into the pattern, all LEDs share one clock so patterns stay in phase */
+    fn level(&self, elapsed: Duration) -> bool {
+        if self.off.is_zero() {
+            return true;
+        }
+        let period = (self.on + self.off).as_secs_f64();
+        elapsed.as_secs_f64() % period < self.on.as_secs_f64()
+    }
+}
+
+/* snapshot of everything the LEDs can show */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChamberState {
+    pub alarm: bool,
+    pub fault: bool,
+    pub stale_sensor: bool,
+    pub heating: bool,
+    pub cooling: bool,
+    pub humidifying: bool,
+    pub venting: bool,
+    pub door_open: bool,
+    pub contamination: bool,
+}
+
+impl ChamberState {
+    fn holds(&self, condition: LedCondition) -> bool {
+        match condition {
+            LedCondition::Healthy => !self.fault && !self.stale_sensor,
+            LedCondition::Alarm => self.alarm,
+            LedCondition::Fault => self.fault,
+            LedCondition::StaleSensor => self.stale_sensor,
+            LedCondition::Heating => self.heating,
+            LedCondition::Cooling => self.cooling,
+            LedCondition::Humidifying => self.humidifying,
+            LedCondition::Venting => self.venting,
+            LedCondition::DoorOpen => self.door_open,
+            LedCondition::Contamination => self.contamination,
+            LedCondition::Always => true,
+        }
+    }
+}
+
+/* alarms are raised from the same state, so it is evaluated here once per tick; before the
+first reading the sensor only counts as stale once `started_at` is long enough ago */
+pub fn chamber_state(now: u64, started_at: u64) -> ChamberState {
+    let stale_after = config::get().indicator.stale_sensor_after.as_secs();
+    let mut state = ChamberState {
+        alarm: false,
+        fault: get_worker_status()
+            .iter()
+            .any(|worker| worker.state == WorkerState::Restarting),
+        stale_sensor: match last_reading_at() {
+            Some(time) => now.saturating_sub(time) > stale_after,
+            None => now.saturating_sub(started_at) > stale_after,
+        },
+        heating: output::is_on(Actuator::Heating).unwrap_or(false),
+        cooling: output::is_on(Actuator::Cooling).unwrap_or(false),
+        humidifying: output::is_on(Actuator::Humidifier).unwrap_or(false),
+        venting: output::is_on(Actuator::Fan).unwrap_or(false),
+        door_open: door::is_open(),
+        contamination: analysis::contamination_detected(),
+    };
+    alarm::update(&state, now);
+    state.alarm = alarm::has_unacknowledged();
+    state
+}
+
+pub fn led_level(rules: &[LedRule], state: &ChamberState, elapsed: Duration) -> bool {
+    match rules.iter().find(|rule| state.holds(rule.when)) {
+        Some(rule) => rule.level(elapsed),
+        None => false,
+    }
+}
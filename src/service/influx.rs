@@ -0,0 +1,216 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Mutex};
+
+use super::{config, database::sensor::HistoricSensorData};
+
+const MAX_BUFFERED_LINES: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InfluxConfig {
+    pub url: String,
+    #[serde(default)]
+    pub org: String,
+    #[serde(default = "default_bucket")]
+    pub bucket: String,
+    #[serde(default)]
+    pub token: String,
+    #[serde(default)]
+    pub chamber: Option<String>,
+}
+
+fn default_bucket() -> String {
+    String::from("fermentation")
+}
+
+lazy_static! {
+    static ref BUFFER: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+}
+
+/* live push is only enabled when an [influx] section is configured */
+pub fn get_config() -> Option<&'static InfluxConfig> {
+    config::get().influx.as_ref()
+}
+
+pub fn sensor_line(data: &HistoricSensorData) -> String {
+    format!(
+        "sensor{} temp={},hum={},door_open={} {}",
+        tags(&[]),
+        data.data.temp,
+        data.data.hum,
+        data.door_open,
+        data.time
+    )
+}
+
+pub fn controller_line(
+    control_loop: &str,
+    target: f32,
+    output: f32,
+    duty_cycle: f32,
+    time: u64,
+) -> String {
+    format!(
+        "controller{} target={},output={},duty_cycle={} {}",
+        tags(&[("loop", control_loop)]),
+        target,
+        output,
+        duty_cycle,
+        time
+    )
+}
+
+fn tags(extra: &[(&str, &str)]) -> String {
+    let mut tags = String::new();
+    if let Some(chamber) = get_config().and_then(|config| config.chamber.as_ref()) {
+        tags.push_str(&format!(",chamber={}", escape_tag(chamber)));
+    }
+    for (key, value) in extra {
+        tags.push_str(&format!(",{}={}", key, escape_tag(value)));
+    }
+    tags
+}
+
+fn escape_tag(value: &str) -> String {
+    value
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/* queue a line for the live pusher, dropping the oldest lines when the endpoint is unreachable for long */
+pub fn queue(line: String) {
+    if get_config().is_none() {
+        return;
+    }
+    if let Ok(mut buffer) = BUFFER.lock() {
+        if buffer.len() >= MAX_BUFFERED_LINES {
+            buffer.pop_front();
+        }
+        buffer.push_back(line);
+    }
+}
+
+pub fn take_batch(max_lines: usize) -> Vec<String> {
+    match BUFFER.lock() {
+        Ok(mut buffer) => {
+            let count = max_lines.min(buffer.len());
+            buffer.drain(..count).collect()
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+/* put a batch that could not be written back in front of the queue */
+pub fn requeue(batch: Vec<String>) {
+    if let Ok(mut buffer) = BUFFER.lock() {
+        for line in batch.into_iter().rev() {
+            if buffer.len() >= MAX_BUFFERED_LINES {
+                break;
+            }
+            buffer.push_front(line);
+        }
+    }
+}
+
+pub fn write_batch(
+    config: &InfluxConfig,
+    batch: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let url = format!("{}/api/v2/write", config.url.trim_end_matches('/'));
+    ureq::post(&url)
+        .query("org", &config.org)
+        .query("bucket", &config.bucket)
+        .query("precision", "s")
+        .set("Authorization", &format!("Token {}", config.token))
+        .set("Content-Type", "text/plain; charset=utf-8")
+        .send_string(&batch.join("\n"))?;
+    Ok(())
+}
+
+/* a 4xx answer (malformed line, bad token or bucket) fails the same way when sent again, so
+only transport errors and 5xx answers are worth a retry */
+pub fn is_retryable(error: &(dyn std::error::Error + 'static)) -> bool {
+    !matches!(
+        error.downcast_ref::<ureq::Error>(),
+        Some(ureq::Error::Status(400..=499, _))
+    )
+}
+
+/* write everything still buffered, used on shutdown */
+pub fn flush() -> Result<(), Box<dyn std::error::Error>> {
+    let config = match get_config() {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    loop {
+        let batch = take_batch(MAX_BUFFERED_LINES);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = write_batch(config, &batch) {
+            if !is_retryable(e.as_ref()) {
+                error!(
+                    "Error: dropping {} lines rejected by influx: {}",
+                    batch.len(),
+                    e
+                );
+                continue;
+            }
+            requeue(batch);
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::database::sensor::SensorData;
+
+    #[test]
+    fn sensor_line_uses_line_protocol() {
+        let data = HistoricSensorData {
+            data: SensorData {
+                temp: 28.5,
+                hum: 80.0,
+            },
+            time: 1700000000,
+            door_open: true,
+        };
+        assert_eq!(
+            sensor_line(&data),
+            "sensor temp=28.5,hum=80,door_open=true 1700000000"
+        );
+    }
+
+    #[test]
+    fn controller_line_tags_the_loop() {
+        assert_eq!(
+            controller_line("temperature", 30.0, 12.5, 0.25, 1700000000),
+            "controller,loop=temperature target=30,output=12.5,duty_cycle=0.25 1700000000"
+        );
+    }
+
+    #[test]
+    fn tag_values_are_escaped() {
+        assert_eq!(escape_tag("chamber 1,a=b"), "chamber\\ 1\\,a\\=b");
+    }
+
+    #[test]
+    fn only_client_errors_are_dropped() {
+        let status = |code| -> Box<dyn std::error::Error> {
+            Box::new(ureq::Error::Status(
+                code,
+                ureq::Response::new(code, "", "").unwrap(),
+            ))
+        };
+        assert!(!is_retryable(status(400).as_ref()));
+        assert!(!is_retryable(status(401).as_ref()));
+        assert!(!is_retryable(status(404).as_ref()));
+        assert!(is_retryable(status(500).as_ref()));
+        assert!(is_retryable(status(503).as_ref()));
+        let transport: Box<dyn std::error::Error> = Box::from("connection refused");
+        assert!(is_retryable(transport.as_ref()));
+    }
+}
@@ -0,0 +1,314 @@
+use lazy_static::lazy_static;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Request, Response,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::Mutex,
+    time::Duration,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Counter {
+    SensorReadAttempts,
+    SensorReadFailures,
+    SensorChecksumErrors,
+    SensorTimeouts,
+    WebcamFrames,
+    WebcamFrameErrors,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Gauge {
+    Temperature,
+    Humidity,
+    TargetTemperature,
+    TargetHumidity,
+    TempPidOutput,
+    CoolingPidOutput,
+    HumPidOutput,
+    FanPidOutput,
+    HeatingDutyCycle,
+    CoolingDutyCycle,
+    HumidifierDutyCycle,
+    FanDutyCycle,
+}
+
+const COUNTERS: [(Counter, &str, &str); 6] = [
+    (
+        Counter::SensorReadAttempts,
+        "fermentation_sensor_read_attempts_total",
+        "Number of attempts to read the DHT sensor.",
+    ),
+    (
+        Counter::SensorReadFailures,
+        "fermentation_sensor_read_failures_total",
+        "Number of sensor reads that returned an error.",
+    ),
+    (
+        Counter::SensorChecksumErrors,
+        "fermentation_sensor_checksum_errors_total",
+        "Number of sensor reads with an invalid checksum.",
+    ),
+    (
+        Counter::SensorTimeouts,
+        "fermentation_sensor_timeouts_total",
+        "Number of sensor reads that timed out waiting for a level change.",
+    ),
+    (
+        Counter::WebcamFrames,
+        "fermentation_webcam_frames_total",
+        "Number of webcam frames captured and stored.",
+    ),
+    (
+        Counter::WebcamFrameErrors,
+        "fermentation_webcam_frame_errors_total",
+        "Number of webcam frame captures that failed.",
+    ),
+];
+
+const GAUGES: [(Gauge, &str, &str); 12] = [
+    (
+        Gauge::Temperature,
+        "fermentation_temperature_celsius",
+        "Last measured chamber temperature.",
+    ),
+    (
+        Gauge::Humidity,
+        "fermentation_humidity_percent",
+        "Last measured chamber relative humidity.",
+    ),
+    (
+        Gauge::TargetTemperature,
+        "fermentation_target_temperature_celsius",
+        "Temperature setpoint of the active project.",
+    ),
+    (
+        Gauge::TargetHumidity,
+        "fermentation_target_humidity_percent",
+        "Humidity setpoint of the active project.",
+    ),
+    (
+        Gauge::TempPidOutput,
+        "fermentation_temperature_pid_output",
+        "Last output of the temperature PID controller.",
+    ),
+    (
+        Gauge::CoolingPidOutput,
+        "fermentation_cooling_pid_output",
+        "Last output of the cooling PID controller.",
+    ),
+    (
+        Gauge::HumPidOutput,
+        "fermentation_humidity_pid_output",
+        "Last output of the humidity PID controller.",
+    ),
+    (
+        Gauge::FanPidOutput,
+        "fermentation_fan_pid_output",
+        "Last output of the fan PID controller.",
+    ),
+    (
+        Gauge::HeatingDutyCycle,
+        "fermentation_heating_duty_cycle_ratio",
+        "Fraction of the last control cycle the heater relay was on.",
+    ),
+    (
+        Gauge::CoolingDutyCycle,
+        "fermentation_cooling_duty_cycle_ratio",
+        "Fraction of the last control cycle the cooling relay was on.",
+    ),
+    (
+        Gauge::HumidifierDutyCycle,
+        "fermentation_humidifier_duty_cycle_ratio",
+        "Fraction of the last control cycle the humidifier relay was on.",
+    ),
+    (
+        Gauge::FanDutyCycle,
+        "fermentation_fan_duty_cycle_ratio",
+        "Fraction of the last control cycle the fan relay was on.",
+    ),
+];
+
+#[derive(Default)]
+struct Registry {
+    counters: HashMap<Counter, u64>,
+    gauges: HashMap<Gauge, f64>,
+    webcam_latency_sum: f64,
+    webcam_latency_count: u64,
+    http_requests: BTreeMap<(String, String, u16), u64>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+pub fn inc(counter: Counter) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        *registry.counters.entry(counter).or_insert(0) += 1;
+    }
+}
+
+pub fn set(gauge: Gauge, value: f64) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.gauges.insert(gauge, value);
+    }
+}
+
+pub fn observe_webcam_latency(latency: Duration) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.webcam_latency_sum += latency.as_secs_f64();
+        registry.webcam_latency_count += 1;
+    }
+}
+
+fn inc_http_request(method: String, route: String, status: u16) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        *registry
+            .http_requests
+            .entry((method, route, status))
+            .or_insert(0) += 1;
+    }
+}
+
+/* render all metrics in the prometheus text exposition format */
+pub fn render() -> Result<String, Box<dyn std::error::Error>> {
+    let registry = match REGISTRY.lock() {
+        Ok(registry) => registry,
+        Err(e) => {
+            error!("Error: {}", e);
+            return Err(Box::from("Metrics registry"));
+        }
+    };
+    render_registry(&registry)
+}
+
+fn render_registry(registry: &Registry) -> Result<String, Box<dyn std::error::Error>> {
+    let mut out = String::new();
+
+    for (counter, name, help) in COUNTERS.iter() {
+        writeln!(out, "# HELP {name} {help}")?;
+        writeln!(out, "# TYPE {name} counter")?;
+        writeln!(
+            out,
+            "{name} {}",
+            registry.counters.get(counter).copied().unwrap_or(0)
+        )?;
+    }
+
+    for (gauge, name, help) in GAUGES.iter() {
+        if let Some(value) = registry.gauges.get(gauge) {
+            writeln!(out, "# HELP {name} {help}")?;
+            writeln!(out, "# TYPE {name} gauge")?;
+            writeln!(out, "{name} {value}")?;
+        }
+    }
+
+    let name = "fermentation_webcam_capture_seconds";
+    writeln!(
+        out,
+        "# HELP {name} Time spent capturing and storing a webcam frame."
+    )?;
+    writeln!(out, "# TYPE {name} summary")?;
+    writeln!(out, "{name}_sum {}", registry.webcam_latency_sum)?;
+    writeln!(out, "{name}_count {}", registry.webcam_latency_count)?;
+
+    let name = "fermentation_http_requests_total";
+    writeln!(out, "# HELP {name} Number of HTTP requests handled.")?;
+    writeln!(out, "# TYPE {name} counter")?;
+    for ((method, route, status), count) in registry.http_requests.iter() {
+        writeln!(
+            out,
+            "{name}{{method=\"{method}\",route=\"{}\",status=\"{status}\"}} {count}",
+            escape_label(route)
+        )?;
+    }
+    Ok(out)
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub struct HttpMetrics;
+
+#[rocket::async_trait]
+impl Fairing for HttpMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "HTTP request metrics",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let route = match request.route() {
+            Some(route) => route.uri.to_string(),
+            None => String::from("unmatched"),
+        };
+        inc_http_request(request.method().to_string(), route, response.status().code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_are_always_rendered() {
+        let mut registry = Registry::default();
+        registry.counters.insert(Counter::WebcamFrames, 3);
+        let out = render_registry(&registry).unwrap();
+        assert!(out.contains(
+            "# HELP fermentation_webcam_frames_total Number of webcam frames captured and stored.\n\
+             # TYPE fermentation_webcam_frames_total counter\n\
+             fermentation_webcam_frames_total 3\n"
+        ));
+        assert!(out.contains("\nfermentation_sensor_timeouts_total 0\n"));
+    }
+
+    #[test]
+    fn gauges_are_only_rendered_once_set() {
+        let mut registry = Registry::default();
+        registry.gauges.insert(Gauge::Temperature, 28.5);
+        let out = render_registry(&registry).unwrap();
+        assert!(out.contains("# TYPE fermentation_temperature_celsius gauge\n"));
+        assert!(out.contains("\nfermentation_temperature_celsius 28.5\n"));
+        assert!(!out.contains("fermentation_humidity_percent"));
+    }
+
+    #[test]
+    fn webcam_latency_is_a_summary() {
+        let registry = Registry {
+            webcam_latency_sum: 1.5,
+            webcam_latency_count: 2,
+            ..Registry::default()
+        };
+        let out = render_registry(&registry).unwrap();
+        assert!(out.contains("# TYPE fermentation_webcam_capture_seconds summary\n"));
+        assert!(out.contains("\nfermentation_webcam_capture_seconds_sum 1.5\n"));
+        assert!(out.contains("\nfermentation_webcam_capture_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn http_requests_are_labelled() {
+        let mut registry = Registry::default();
+        registry
+            .http_requests
+            .insert((String::from("GET"), String::from("/project/<id>"), 200), 4);
+        let out = render_registry(&registry).unwrap();
+        assert!(out.contains(
+            "\nfermentation_http_requests_total{method=\"GET\",route=\"/project/<id>\",status=\"200\"} 4\n"
+        ));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
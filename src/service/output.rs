@@ -0,0 +1,483 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::config::{self, seconds};
+
+const HOUR: Duration = Duration::from_secs(3600);
+/* a demand the control loop did not refresh for this many of its intervals is dropped */
+const DEMAND_TIMEOUT_INTERVALS: u32 = 5;
+const DEMAND_TIMEOUT_MIN: Duration = Duration::from_secs(10);
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Actuator {
+    Heating,
+    Cooling,
+    Humidifier,
+    Fan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    #[serde(with = "seconds")]
+    pub window: Duration,
+    #[serde(with = "seconds")]
+    pub min_on: Duration,
+    #[serde(with = "seconds")]
+    pub min_off: Duration,
+    /* counts switching on only, switching off is never held back by it */
+    pub max_switches_per_hour: usize,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            window: Duration::from_secs(20),
+            min_on: Duration::from_secs(2),
+            min_off: Duration::from_secs(2),
+            max_switches_per_hour: 360,
+        }
+    }
+}
+
+impl OutputConfig {
+    /* a compressor needs a long window and minimum off time */
+    pub fn compressor() -> Self {
+        OutputConfig {
+            window: Duration::from_secs(600),
+            min_on: Duration::from_secs(120),
+            min_off: Duration::from_secs(300),
+            max_switches_per_hour: 6,
+        }
+    }
+}
+
+/* time-proportioning relay driver: the demanded duty is turned into one on period per window,
+respecting minimum on/off times and a budget of switch-ons per hour. Demand that is too small to
+switch on for min_on is carried over to the following windows. With a demand timeout, a demand
+that is not refreshed in time is dropped, so the output fails safe when its control loop stops. */
+pub struct OutputDriver<C: Clock> {
+    config: OutputConfig,
+    clock: C,
+    demand: f32,
+    demand_set: Instant,
+    demand_timeout: Option<Duration>,
+    on: bool,
+    window_start: Instant,
+    planned_on: Duration,
+    /* on seconds the current window should deliver, before rounding to min_on and min_off */
+    target: f64,
+    on_accumulated: Duration,
+    last_update: Instant,
+    last_switch: Instant,
+    switches: VecDeque<Instant>,
+    delivered: f32,
+}
+
+impl<C: Clock> OutputDriver<C> {
+    pub fn new(config: OutputConfig, clock: C) -> Self {
+        let now = clock.now();
+        OutputDriver {
+            config,
+            clock,
+            demand: 0.0,
+            demand_set: now,
+            demand_timeout: None,
+            on: false,
+            /* start with an expired window so the first update plans one */
+            window_start: now.checked_sub(config.window).unwrap_or(now),
+            planned_on: Duration::ZERO,
+            target: 0.0,
+            on_accumulated: Duration::ZERO,
+            last_update: now,
+            /* the output may have been on right before a restart, so min_off applies from here */
+            last_switch: now,
+            switches: VecDeque::new(),
+            delivered: 0.0,
+        }
+    }
+
+    pub fn with_demand_timeout(mut self, timeout: Duration) -> Self {
+        self.demand_timeout = Some(timeout);
+        self
+    }
+
+    /* duty between 0 and 1, applied from the next window on */
+    pub fn set_demand(&mut self, duty: f32) {
+        self.demand = duty.clamp(0.0, 1.0);
+        self.demand_set = self.clock.now();
+    }
+
+    pub fn demand(&self) -> f32 {
+        self.demand
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /* fraction of the last completed window the output was actually on */
+    pub fn delivered_duty(&self) -> f32 {
+        self.delivered
+    }
+
+    pub fn config(&self) -> &OutputConfig {
+        &self.config
+    }
+
+    /* advance to the current time and return whether the output should be on */
+    pub fn update(&mut self) -> bool {
+        let now = self.clock.now();
+        self.expire_demand(now);
+        if self.on {
+            self.on_accumulated += now.saturating_duration_since(self.last_update);
+        }
+        self.last_update = now;
+
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= self.config.window {
+            self.delivered = (self.on_accumulated.as_secs_f64() / elapsed.as_secs_f64()) as f32;
+            let window = self.config.window.as_secs_f64();
+            let carry = (self.target - self.on_accumulated.as_secs_f64()).clamp(-window, window);
+            self.on_accumulated = Duration::ZERO;
+            self.window_start = now;
+            self.target = self.demand as f64 * window + carry;
+            self.planned_on = self.plan(self.target);
+        }
+
+        let want_on = now.saturating_duration_since(self.window_start) < self.planned_on;
+        if want_on != self.on && self.can_switch(now) {
+            self.on = want_on;
+            self.last_switch = now;
+            if want_on {
+                self.switches.push_back(now);
+            }
+        }
+        self.on
+    }
+
+    /* PWM outputs apply the demand as duty directly, it counts as delivered right away */
+    pub fn pass_through(&mut self) -> f32 {
+        self.expire_demand(self.clock.now());
+        self.delivered = self.demand;
+        self.on = self.demand > 0.0;
+        self.demand
+    }
+
+    /* force the output off, e.g. on shutdown, ignoring the minimum on time */
+    pub fn force_off(&mut self) {
+        self.demand = 0.0;
+        self.planned_on = Duration::ZERO;
+        self.target = 0.0;
+        if self.on {
+            self.on = false;
+            self.last_switch = self.clock.now();
+        }
+    }
+
+    /* a stale demand also ends the current on period, min_on still applies */
+    fn expire_demand(&mut self, now: Instant) {
+        let timeout = match self.demand_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        if self.demand > 0.0 && now.saturating_duration_since(self.demand_set) >= timeout {
+            warn!(
+                "demand of {} not refreshed for {:?}, dropping it",
+                self.demand, timeout
+            );
+            self.demand = 0.0;
+            self.planned_on = Duration::ZERO;
+            self.target = 0.0;
+        }
+    }
+
+    fn plan(&self, on_seconds: f64) -> Duration {
+        let window = self.config.window.as_secs_f64();
+        let on_seconds = on_seconds.clamp(0.0, window);
+        if on_seconds < self.config.min_on.as_secs_f64() {
+            Duration::ZERO
+        } else if window - on_seconds < self.config.min_off.as_secs_f64() {
+            self.config.window
+        } else {
+            Duration::from_secs_f64(on_seconds)
+        }
+    }
+
+    /* an output that used up its budget must still be able to switch off, otherwise a heater
+    would stay on for up to an hour */
+    fn can_switch(&mut self, now: Instant) -> bool {
+        while let Some(switch) = self.switches.front() {
+            if now.saturating_duration_since(*switch) >= HOUR {
+                self.switches.pop_front();
+            } else {
+                break;
+            }
+        }
+        if !self.on && self.switches.len() >= self.config.max_switches_per_hour {
+            return false;
+        }
+        let hold = if self.on {
+            self.config.min_on
+        } else {
+            self.config.min_off
+        };
+        now.saturating_duration_since(self.last_switch) >= hold
+    }
+}
+
+lazy_static! {
+    static ref DRIVERS: Mutex<HashMap<Actuator, OutputDriver<SystemClock>>> = {
+        let control = &config::get().control;
+        let temp_timeout = demand_timeout(control.temp_interval);
+        let hum_timeout = demand_timeout(control.hum_interval);
+        let mut m = HashMap::new();
+        m.insert(
+            Actuator::Heating,
+            OutputDriver::new(control.heating, SystemClock).with_demand_timeout(temp_timeout),
+        );
+        m.insert(
+            Actuator::Cooling,
+            OutputDriver::new(control.cooling, SystemClock).with_demand_timeout(temp_timeout),
+        );
+        m.insert(
+            Actuator::Humidifier,
+            OutputDriver::new(control.humidifier, SystemClock).with_demand_timeout(hum_timeout),
+        );
+        m.insert(
+            Actuator::Fan,
+            OutputDriver::new(control.fan, SystemClock).with_demand_timeout(hum_timeout),
+        );
+        Mutex::new(m)
+    };
+}
+
+fn demand_timeout(loop_interval: Duration) -> Duration {
+    (loop_interval * DEMAND_TIMEOUT_INTERVALS).max(DEMAND_TIMEOUT_MIN)
+}
+
+fn with_driver<T>(
+    actuator: Actuator,
+    f: impl FnOnce(&mut OutputDriver<SystemClock>) -> T,
+) -> Result<T, Box<dyn std::error::Error>> {
+    match DRIVERS.lock() {
+        Ok(mut drivers) => match drivers.get_mut(&actuator) {
+            Some(driver) => Ok(f(driver)),
+            None => Err(Box::from("Output driver not found")),
+        },
+        Err(e) => {
+            error!("Error: {}", e);
+            Err(Box::from("Output driver"))
+        }
+    }
+}
+
+pub fn set_demand(actuator: Actuator, duty: f32) -> Result<(), Box<dyn std::error::Error>> {
+    with_driver(actuator, |driver| driver.set_demand(duty))
+}
+
+pub fn update(actuator: Actuator) -> Result<bool, Box<dyn std::error::Error>> {
+    with_driver(actuator, |driver| driver.update())
+}
+
+pub fn pass_through(actuator: Actuator) -> Result<f32, Box<dyn std::error::Error>> {
+    with_driver(actuator, |driver| driver.pass_through())
+}
+
+pub fn force_off(actuator: Actuator) -> Result<(), Box<dyn std::error::Error>> {
+    with_driver(actuator, |driver| driver.force_off())
+}
+
+pub fn is_on(actuator: Actuator) -> Result<bool, Box<dyn std::error::Error>> {
+    with_driver(actuator, |driver| driver.is_on())
+}
+
+pub fn delivered_duty(actuator: Actuator) -> Result<f32, Box<dyn std::error::Error>> {
+    with_driver(actuator, |driver| driver.delivered_duty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<Instant>>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    impl FakeClock {
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    fn config(window: u64, min_on: u64, min_off: u64, switches: usize) -> OutputConfig {
+        OutputConfig {
+            window: Duration::from_secs(window),
+            min_on: Duration::from_secs(min_on),
+            min_off: Duration::from_secs(min_off),
+            max_switches_per_hour: switches,
+        }
+    }
+
+    /* min_off applies right after start, so the clock starts past it */
+    fn driver(config: OutputConfig, demand: f32) -> (OutputDriver<FakeClock>, FakeClock) {
+        let clock = FakeClock(Rc::new(Cell::new(Instant::now())));
+        let mut driver = OutputDriver::new(config, clock.clone());
+        driver.set_demand(demand);
+        clock.advance(config.min_off);
+        (driver, clock)
+    }
+
+    /* the output level of every second */
+    fn run(driver: &mut OutputDriver<FakeClock>, clock: &FakeClock, seconds: usize) -> Vec<bool> {
+        (0..seconds)
+            .map(|_| {
+                let on = driver.update();
+                clock.advance(Duration::from_secs(1));
+                on
+            })
+            .collect()
+    }
+
+    fn on_seconds(levels: &[bool]) -> usize {
+        levels.iter().filter(|on| **on).count()
+    }
+
+    #[test]
+    fn demand_is_one_on_period_per_window() {
+        let (mut driver, clock) = driver(config(20, 2, 2, 360), 0.5);
+        let levels = run(&mut driver, &clock, 40);
+        assert!(levels[..10].iter().all(|on| *on));
+        assert!(levels[10..20].iter().all(|on| !on));
+        assert!(levels[20..30].iter().all(|on| *on));
+        assert!(levels[30..].iter().all(|on| !on));
+        assert_eq!(driver.delivered_duty(), 0.5);
+    }
+
+    #[test]
+    fn small_demand_is_carried_over_until_min_on() {
+        let (mut driver, clock) = driver(config(16, 2, 2, 360), 0.0625);
+        let levels = run(&mut driver, &clock, 64);
+        assert_eq!(on_seconds(&levels[..16]), 0);
+        assert_eq!(on_seconds(&levels[16..32]), 2);
+        assert!(levels[16] && levels[17]);
+        assert_eq!(on_seconds(&levels[32..48]), 0);
+        assert_eq!(on_seconds(&levels[48..64]), 2);
+    }
+
+    #[test]
+    fn off_period_shorter_than_min_off_keeps_the_output_on() {
+        let (mut driver, clock) = driver(config(20, 2, 2, 360), 0.95);
+        let levels = run(&mut driver, &clock, 40);
+        assert!(levels[..20].iter().all(|on| *on));
+        /* the extra second of the first window is taken off the second one */
+        assert_eq!(on_seconds(&levels), 38);
+    }
+
+    #[test]
+    fn demand_changes_apply_from_the_next_window() {
+        let (mut driver, clock) = driver(config(20, 2, 2, 360), 0.25);
+        assert!(run(&mut driver, &clock, 3).iter().all(|on| *on));
+        driver.set_demand(0.0);
+        assert_eq!(on_seconds(&run(&mut driver, &clock, 17)), 2);
+        assert_eq!(on_seconds(&run(&mut driver, &clock, 20)), 0);
+    }
+
+    #[test]
+    fn min_off_and_min_on_hold_the_output() {
+        let clock = FakeClock(Rc::new(Cell::new(Instant::now())));
+        let mut driver = OutputDriver::new(config(20, 5, 5, 360), clock.clone());
+        driver.set_demand(0.3);
+        /* min_off counts from the start, then min_on outlasts the planned 6 seconds */
+        let levels = run(&mut driver, &clock, 20);
+        assert!(levels[..5].iter().all(|on| !on));
+        assert!(levels[5..10].iter().all(|on| *on));
+        assert!(levels[10..].iter().all(|on| !on));
+    }
+
+    #[test]
+    fn switch_budget_limits_switching_on_only() {
+        let (mut driver, clock) = driver(config(20, 2, 2, 1), 0.5);
+        let levels = run(&mut driver, &clock, 3600);
+        /* the budget is used up by the first switch-on, switching off is still allowed */
+        assert!(levels[..10].iter().all(|on| *on));
+        assert!(levels[10..].iter().all(|on| !on));
+        /* an hour after the first switch-on the budget is back */
+        assert!(run(&mut driver, &clock, 10).iter().all(|on| *on));
+    }
+
+    #[test]
+    fn switch_budget_counts_switch_ons_per_hour() {
+        let (mut driver, clock) = driver(config(20, 2, 2, 2), 0.5);
+        let levels = run(&mut driver, &clock, 60);
+        assert_eq!(on_seconds(&levels[..40]), 20);
+        assert_eq!(on_seconds(&levels[40..]), 0);
+    }
+
+    #[test]
+    fn stale_demand_is_dropped() {
+        let (driver, clock) = driver(config(20, 2, 2, 360), 0.0);
+        let mut driver = driver.with_demand_timeout(Duration::from_secs(5));
+        driver.set_demand(1.0);
+        let levels = run(&mut driver, &clock, 20);
+        /* on until the timeout, it also ends the planned on period of the window */
+        assert!(levels[..5].iter().all(|on| *on));
+        assert!(levels[5..].iter().all(|on| !on));
+        assert_eq!(driver.demand(), 0.0);
+    }
+
+    #[test]
+    fn refreshed_demand_is_kept() {
+        let (driver, clock) = driver(config(20, 2, 2, 360), 0.0);
+        let mut driver = driver.with_demand_timeout(Duration::from_secs(5));
+        let mut levels = Vec::new();
+        for _ in 0..10 {
+            driver.set_demand(1.0);
+            levels.extend(run(&mut driver, &clock, 4));
+        }
+        assert!(levels.iter().all(|on| *on));
+    }
+
+    #[test]
+    fn stale_pwm_demand_is_dropped() {
+        let (driver, clock) = driver(config(20, 2, 2, 360), 0.0);
+        let mut driver = driver.with_demand_timeout(Duration::from_secs(5));
+        driver.set_demand(0.5);
+        assert_eq!(driver.pass_through(), 0.5);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(driver.pass_through(), 0.0);
+    }
+
+    #[test]
+    fn demand_timeout_follows_the_loop_interval() {
+        assert_eq!(demand_timeout(Duration::from_secs(1)), DEMAND_TIMEOUT_MIN);
+        assert_eq!(
+            demand_timeout(Duration::from_secs(30)),
+            Duration::from_secs(150)
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use chrono::{TimeZone, Utc};
+use image::{ImageBuffer, Pixel};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    database::{
+        project::{Project, Settings},
+        sensor::{read_day, HistoricSensorData, SensorData},
+    },
+    export::settings_at,
+    sensor::{last_reading_at, last_sensor_data},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OverlaySettings {
+    /* burn the overlay into every stored frame, it can't be removed afterwards */
+    pub frames: bool,
+    /* default for timelapse requests, drawn from the recorded sensor history */
+    pub timelapse: bool,
+    /* size of a font dot in pixels */
+    pub scale: u32,
+}
+
+impl Default for OverlaySettings {
+    fn default() -> Self {
+        OverlaySettings {
+            frames: false,
+            timelapse: false,
+            scale: 2,
+        }
+    }
+}
+
+/* a live reading older than this is not shown */
+const MAX_READING_AGE: u64 = 60;
+/* history is aggregated to 15 minutes after a while, a reading further away is not shown */
+const MAX_READING_DISTANCE: u64 = 900;
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+const LINE_SPACING: u32 = 2;
+
+/* everything the overlay shows for one frame */
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlayData {
+    pub time: u64,
+    pub label: String,
+    pub started_at: Option<u64>,
+    pub reading: Option<SensorData>,
+    pub settings: Settings,
+}
+
+impl OverlayData {
+    /* for a frame captured right now */
+    pub fn live(project: &Project) -> OverlayData {
+        let time = Utc::now().timestamp() as u64;
+        let fresh = last_reading_at().is_some_and(|at| time.saturating_sub(at) <= MAX_READING_AGE);
+        OverlayData {
+            time,
+            label: project.name.clone(),
+            started_at: project.start_at,
+            reading: if fresh { last_sensor_data() } else { None },
+            settings: project.settings.clone(),
+        }
+    }
+
+    /* for a recorded frame, looked up in the sensor history */
+    pub fn recorded(project: &Project, time: u64, history: &mut SensorHistory) -> OverlayData {
+        OverlayData {
+            time,
+            label: project.name.clone(),
+            started_at: project.start_at,
+            reading: history.reading_at(time),
+            settings: settings_at(project, time),
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![match Utc.timestamp_opt(self.time as i64, 0).single() {
+            Some(time) => time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            None => String::new(),
+        }];
+        if !self.label.is_empty() {
+            lines.push(self.label.clone());
+        }
+        if let Some(started_at) = self.started_at {
+            let elapsed = self.time.saturating_sub(started_at);
+            lines.push(format!(
+                "Day {} {:02}:{:02}",
+                elapsed / 86400,
+                elapsed % 86400 / 3600,
+                elapsed % 3600 / 60
+            ));
+        }
+        lines.push(match self.reading {
+            Some(reading) => format!("{:.1}°C {:.1}%", reading.temp, reading.hum),
+            None => String::from("--.-°C --.-%"),
+        });
+        lines.push(format!(
+            "Set {:.1}°C {:.1}%",
+            self.settings.temp, self.settings.hum
+        ));
+        lines
+    }
+}
+
+/* sensor readings of one day at a time, frames are rendered in order so a day is read once */
+#[derive(Debug, Default)]
+pub struct SensorHistory {
+    day: Option<u64>,
+    readings: Vec<HistoricSensorData>,
+}
+
+impl SensorHistory {
+    fn reading_at(&mut self, time: u64) -> Option<SensorData> {
+        let day = time - time % 86400;
+        if self.day != Some(day) {
+            self.readings = match read_day(day) {
+                Ok(readings) => readings,
+                Err(e) => {
+                    error!("Error: {}", e);
+                    Vec::new()
+                }
+            };
+            self.day = Some(day);
+        }
+        self.readings
+            .iter()
+            .min_by_key(|reading| reading.time.abs_diff(time))
+            .filter(|reading| reading.time.abs_diff(time) <= MAX_READING_DISTANCE)
+            .map(|reading| reading.data)
+    }
+}
+
+/* white text on a darkened box in the top left corner */
+pub fn draw<P>(image: &mut ImageBuffer<P, Vec<u8>>, data: &OverlayData, scale: u32)
+where
+    P: Pixel<Subpixel = u8> + 'static,
+{
+    let lines = data.lines();
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let line_height = (GLYPH_HEIGHT + LINE_SPACING) * scale;
+    let columns = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0) as u32;
+    let box_width = (columns * advance + 2 * scale).min(image.width());
+    let box_height = (lines.len() as u32 * line_height + scale).min(image.height());
+    for y in 0..box_height {
+        for x in 0..box_width {
+            image
+                .get_pixel_mut(x, y)
+                .apply_without_alpha(|value| value / 3);
+        }
+    }
+    for (row, line) in lines.iter().enumerate() {
+        let top = scale + row as u32 * line_height;
+        for (column, character) in line.chars().enumerate() {
+            let left = scale + column as u32 * advance;
+            for (x, bits) in glyph(character).iter().enumerate() {
+                for y in 0..GLYPH_HEIGHT {
+                    if bits & (1 << y) != 0 {
+                        fill(image, left + x as u32 * scale, top + y * scale, scale);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn fill<P>(image: &mut ImageBuffer<P, Vec<u8>>, left: u32, top: u32, size: u32)
+where
+    P: Pixel<Subpixel = u8> + 'static,
+{
+    for y in top..(top + size).min(image.height()) {
+        for x in left..(left + size).min(image.width()) {
+            image.get_pixel_mut(x, y).apply_without_alpha(|_| u8::MAX);
+        }
+    }
+}
+
+/* columns of a glyph from left to right, bit 0 is the top row */
+fn glyph(character: char) -> [u8; 5] {
+    match character {
+        '°' => DEGREE,
+        ' '..='~' => FONT[character as usize - ' ' as usize],
+        _ => FONT['?' as usize - ' ' as usize],
+    }
+}
+
+const DEGREE: [u8; 5] = [0x00, 0x06, 0x09, 0x09, 0x06];
+
+/* 5x7 bitmap font for printable ASCII */
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // '#'
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '\''
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // ')'
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], // '*'
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // '0'
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // '@'
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // 'A'
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // 'D'
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // 'F'
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // 'G'
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // 'H'
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // 'J'
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // 'M'
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // 'N'
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // 'O'
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // 'Q'
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // 'T'
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // 'U'
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // 'V'
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x07, 0x08, 0x70, 0x08, 0x07], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\\'
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // 'f'
+    [0x0C, 0x52, 0x52, 0x52, 0x3E], // 'g'
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // 'j'
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // 'l'
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // 'q'
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // 't'
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // 'u'
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // 'v'
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // 'y'
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
@@ -0,0 +1,276 @@
+use glob::glob;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+};
+
+use super::{
+    config,
+    database::{
+        project::{read_projects, Project},
+        sensor::{HistoricSensorData, SensorData},
+    },
+    export::project_range,
+    webcam::project_frames,
+};
+
+const THINNED_MARKER: &str = ".thinned";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /* raw 1 Hz readings are kept this many days */
+    pub raw_days: u64,
+    /* 1-minute aggregates are kept this many days, older data is kept as 15-minute aggregates */
+    pub minute_days: u64,
+    /* after a project ended only every nth webcam frame is kept */
+    pub frame_stride: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    pub project: u32,
+    pub sensor_bytes: u64,
+    pub webcam_bytes: u64,
+    pub webcam_frames: u64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            raw_days: 14,
+            minute_days: 90,
+            frame_stride: 60,
+        }
+    }
+}
+
+pub fn get_policy() -> &'static RetentionPolicy {
+    &config::get().retention
+}
+
+/* replace old day files with 1-minute or 15-minute averages, skipping days of kept projects */
+pub fn downsample_sensor_data(now: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = get_policy();
+    let kept = kept_ranges()?;
+    for path in glob(sensor_pattern()?.as_str())? {
+        let path = path?;
+        let day = match day_of(&path) {
+            Some(day) => day,
+            None => continue,
+        };
+        let bucket = match bucket_for(policy, now.saturating_sub(day) / 86400) {
+            Some(bucket) => bucket,
+            None => continue,
+        };
+        if kept
+            .iter()
+            .any(|(start, end)| *start < day + 86400 && *end >= day)
+        {
+            continue;
+        }
+        downsample_file(&path, bucket)?;
+    }
+    Ok(())
+}
+
+/* aggregation interval in seconds for a day file of this age, none while raw data is kept */
+fn bucket_for(policy: &RetentionPolicy, age_days: u64) -> Option<u64> {
+    if age_days > policy.minute_days {
+        Some(900)
+    } else if age_days > policy.raw_days {
+        Some(60)
+    } else {
+        None
+    }
+}
+
+fn kept_ranges() -> Result<Vec<(u64, u64)>, Box<dyn std::error::Error>> {
+    Ok(read_projects()?
+        .iter()
+        .filter(|project| project.keep)
+        .filter_map(project_range)
+        .collect())
+}
+
+fn sensor_pattern() -> Result<String, Box<dyn std::error::Error>> {
+    match config::get().paths.sensor_dir().join("*.json").to_str() {
+        Some(pattern) => Ok(pattern.to_string()),
+        None => Err(Box::from("Invalid path")),
+    }
+}
+
+fn day_of(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let date = chrono::NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp() as u64)
+}
+
+fn downsample_file(path: &Path, bucket: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = String::new();
+    File::open(path)?.read_to_string(&mut data)?;
+    let readings: Vec<HistoricSensorData> = serde_json::from_str(&data)?;
+    let aggregated = aggregate(&readings, bucket);
+    if aggregated.len() == readings.len() {
+        return Ok(());
+    }
+
+    let tmp_path = path.with_extension("json.tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(serde_json::to_string(&aggregated)?.as_bytes())?;
+    fs::rename(tmp_path, path)?;
+    info!(
+        "downsampled {:?} from {} to {} readings",
+        path,
+        readings.len(),
+        aggregated.len()
+    );
+    Ok(())
+}
+
+/* average the readings of each bucket, dated to its start; the door counts as open when it was
+open at any reading of the bucket */
+fn aggregate(readings: &[HistoricSensorData], bucket: u64) -> Vec<HistoricSensorData> {
+    let mut aggregated: Vec<HistoricSensorData> = Vec::new();
+    let mut count = 0.0;
+    for reading in readings.iter() {
+        let time = reading.time - reading.time % bucket;
+        match aggregated.last_mut() {
+            Some(last) if last.time == time => {
+                count += 1.0;
+                last.data.temp += (reading.data.temp - last.data.temp) / count;
+                last.data.hum += (reading.data.hum - last.data.hum) / count;
+                last.door_open |= reading.door_open;
+            }
+            _ => {
+                count = 1.0;
+                aggregated.push(HistoricSensorData {
+                    time,
+                    data: SensorData {
+                        temp: reading.data.temp,
+                        hum: reading.data.hum,
+                    },
+                    door_open: reading.door_open,
+                });
+            }
+        }
+    }
+    aggregated
+}
+
+/* keep every nth frame of ended projects once, a marker file prevents thinning again */
+pub fn thin_webcam_frames() -> Result<(), Box<dyn std::error::Error>> {
+    let stride = get_policy().frame_stride.max(1);
+    for project in read_projects()? {
+        if project.keep || project.endend_at.is_none() {
+            continue;
+        }
+        let dir = config::get().paths.webcam_dir(project.id);
+        if !dir.exists() || dir.join(THINNED_MARKER).exists() {
+            continue;
+        }
+        let frames = project_frames(project.id)?;
+        let mut removed = 0;
+        for (index, frame) in frames.iter().enumerate() {
+            if index % stride != 0 {
+                fs::remove_file(frame)?;
+                removed += 1;
+            }
+        }
+        File::create(dir.join(THINNED_MARKER))?;
+        info!(
+            "thinned webcam frames of project {}: removed {} of {}",
+            project.id,
+            removed,
+            frames.len()
+        );
+    }
+    Ok(())
+}
+
+pub fn disk_usage(project: &Project) -> Result<DiskUsage, Box<dyn std::error::Error>> {
+    let mut usage = DiskUsage {
+        project: project.id,
+        sensor_bytes: 0,
+        webcam_bytes: 0,
+        webcam_frames: 0,
+    };
+    if let Some((start, end)) = project_range(project) {
+        for path in glob(sensor_pattern()?.as_str())? {
+            let path = path?;
+            match day_of(&path) {
+                Some(day) if day + 86400 > start && day <= end => {
+                    usage.sensor_bytes += fs::metadata(&path)?.len();
+                }
+                _ => {}
+            }
+        }
+    }
+    for frame in project_frames(project.id)? {
+        usage.webcam_bytes += fs::metadata(&frame)?.len();
+        usage.webcam_frames += 1;
+    }
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn reading(time: u64, temp: f32, hum: f32, door_open: bool) -> HistoricSensorData {
+        HistoricSensorData {
+            data: SensorData { temp, hum },
+            time,
+            door_open,
+        }
+    }
+
+    #[test]
+    fn readings_are_averaged_per_bucket() {
+        let readings = vec![
+            reading(60, 20.0, 80.0, false),
+            reading(80, 22.0, 70.0, true),
+            reading(119, 24.0, 90.0, false),
+            reading(120, 30.0, 60.0, false),
+        ];
+        let aggregated = aggregate(&readings, 60);
+        assert_eq!(
+            aggregated,
+            vec![
+                reading(60, 22.0, 80.0, true),
+                reading(120, 30.0, 60.0, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregated_data_is_not_changed_again() {
+        let readings = vec![
+            reading(0, 20.0, 80.0, false),
+            reading(60, 21.0, 81.0, false),
+        ];
+        assert_eq!(aggregate(&readings, 60), readings);
+    }
+
+    #[test]
+    fn bucket_grows_with_age() {
+        let policy = RetentionPolicy::default();
+        assert_eq!(bucket_for(&policy, 0), None);
+        assert_eq!(bucket_for(&policy, 14), None);
+        assert_eq!(bucket_for(&policy, 15), Some(60));
+        assert_eq!(bucket_for(&policy, 90), Some(60));
+        assert_eq!(bucket_for(&policy, 91), Some(900));
+    }
+
+    #[test]
+    fn day_is_read_from_the_file_name() {
+        assert_eq!(
+            day_of(&PathBuf::from("db/sensor/2024-03-01.json")),
+            Some(1709251200)
+        );
+        assert_eq!(day_of(&PathBuf::from("db/sensor/notes.json")), None);
+    }
+}
@@ -1,17 +1,26 @@
-use super::{database::sensor::SensorData, gpio::read_sensor_data};
-const MAX_RETRIES: u8 = 5;
-const MAX_TEMP: f32 = 50.0;
-const MIN_TEMP: f32 = 0.0;
-const MAX_HUM: f32 = 100.0;
-const MIN_HUM: f32 = 0.0;
+use super::{
+    config::{self, SensorConfig},
+    database::sensor::SensorData,
+    gpio::read_sensor_data,
+    metrics::{self, Gauge},
+};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+/* unix time of the last reading that passed the sanity check, 0 before the first one */
+static LAST_READING: AtomicU64 = AtomicU64::new(0);
+static LAST_DATA: Mutex<Option<SensorData>> = Mutex::new(None);
 
 pub fn get_sensor_data() -> Result<SensorData, Box<dyn std::error::Error>> {
+    let limits = &config::get().sensor;
     let mut retries = 0;
     loop {
         let sensor_result = read_sensor_data();
         if sensor_result.is_err() {
             retries += 1;
-            if retries >= MAX_RETRIES {
+            if retries >= limits.max_retries {
                 let error = sensor_result.err().unwrap();
                 return Err(Box::from(format!("Max retries exceeded: {error}")));
             }
@@ -22,27 +31,51 @@ pub fn get_sensor_data() -> Result<SensorData, Box<dyn std::error::Error>> {
             temp: temperature,
             hum: humidity,
         };
-        match sanity_check_sensor_data(&sensor_data) {
+        match sanity_check_sensor_data(&sensor_data, limits) {
             Ok(_) => {}
             Err(e) => {
                 error!("Error: {}", e);
                 retries += 1;
-                if retries >= MAX_RETRIES {
+                if retries >= limits.max_retries {
                     return Err(Box::from(format!("Max retries exceeded: {e}")));
                 }
                 continue;
             }
         }
+        metrics::set(Gauge::Temperature, sensor_data.temp as f64);
+        metrics::set(Gauge::Humidity, sensor_data.hum as f64);
+        if let Ok(mut last) = LAST_DATA.lock() {
+            *last = Some(sensor_data);
+        }
+        LAST_READING.store(chrono::Utc::now().timestamp() as u64, Ordering::SeqCst);
         return Ok(sensor_data);
     }
 }
 
-fn sanity_check_sensor_data(sensor_data: &SensorData) -> Result<(), Box<dyn std::error::Error>> {
-    if sensor_data.temp > MAX_TEMP || sensor_data.temp < MIN_TEMP {
+fn sanity_check_sensor_data(
+    sensor_data: &SensorData,
+    limits: &SensorConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if sensor_data.temp > limits.max_temp || sensor_data.temp < limits.min_temp {
         return Err(Box::from("Sanity check failed"));
     }
-    if sensor_data.hum > MAX_HUM || sensor_data.hum < MIN_HUM {
+    if sensor_data.hum > limits.max_hum || sensor_data.hum < limits.min_hum {
         return Err(Box::from("Sanity check failed"));
     }
     Ok(())
 }
+
+/* the last reading that passed the sanity check, without touching the sensor */
+pub fn last_sensor_data() -> Option<SensorData> {
+    match LAST_DATA.lock() {
+        Ok(last) => *last,
+        Err(_) => None,
+    }
+}
+
+pub fn last_reading_at() -> Option<u64> {
+    match LAST_READING.load(Ordering::SeqCst) {
+        0 => None,
+        time => Some(time),
+    }
+}
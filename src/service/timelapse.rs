@@ -0,0 +1,786 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::Mutex,
+    thread::{self, JoinHandle},
+};
+
+use engiffen::{engiffen, Quantizer};
+use glob::glob;
+use image::{imageops::FilterType, GenericImageView, RgbaImage};
+
+use super::{
+    config,
+    database::project::read_project,
+    overlay::{self, OverlayData, SensorHistory},
+    webcam::{frame_time, open_frame, project_frames},
+};
+use crate::basic_runners::supervisor::is_shutting_down;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimelapseSettings {
+    /* longer projects are subsampled evenly down to this many frames */
+    pub frames: usize,
+    pub fps: u32,
+    /* frames are scaled down to fit, keeping their aspect ratio */
+    pub width: u32,
+    pub height: u32,
+    /* requests asking for more are clamped to these */
+    pub max_frames: usize,
+    pub max_width: u32,
+    pub max_height: u32,
+    /* a GIF keeps all frames in memory until encoding, frames beyond this many megapixels in
+    total are left out */
+    pub max_gif_megapixels: u32,
+    /* used for MP4 and WebM output */
+    pub ffmpeg: PathBuf,
+}
+
+impl Default for TimelapseSettings {
+    fn default() -> Self {
+        TimelapseSettings {
+            frames: 100,
+            fps: 10,
+            width: 640,
+            height: 480,
+            max_frames: 1000,
+            max_width: 1920,
+            max_height: 1080,
+            max_gif_megapixels: 100,
+            ffmpeg: PathBuf::from("ffmpeg"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelapseFormat {
+    #[default]
+    Gif,
+    /* H.264 */
+    Mp4,
+    /* VP9 */
+    Webm,
+}
+
+impl TimelapseFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            TimelapseFormat::Gif => "gif",
+            TimelapseFormat::Mp4 => "mp4",
+            TimelapseFormat::Webm => "webm",
+        }
+    }
+}
+
+/* what a client asks for, everything left out falls back to the [timelapse] settings */
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TimelapseRequest {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+    pub frames: Option<usize>,
+    pub fps: Option<u32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub format: Option<TimelapseFormat>,
+    pub overlay: Option<bool>,
+}
+
+/* a fully resolved request, equal parameters share one job and its output */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TimelapseParams {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+    pub frames: usize,
+    pub fps: u32,
+    pub width: u32,
+    pub height: u32,
+    pub format: TimelapseFormat,
+    pub overlay: bool,
+}
+
+impl TimelapseRequest {
+    pub fn resolve(&self) -> Result<TimelapseParams, Box<dyn std::error::Error>> {
+        self.resolve_with(&config::get().timelapse, config::get().overlay.timelapse)
+    }
+
+    /* anyone can ask for a render, so the size of the job is bounded by the settings */
+    fn resolve_with(
+        &self,
+        settings: &TimelapseSettings,
+        overlay: bool,
+    ) -> Result<TimelapseParams, Box<dyn std::error::Error>> {
+        let mut params = TimelapseParams {
+            start: self.start,
+            end: self.end,
+            frames: self
+                .frames
+                .unwrap_or(settings.frames)
+                .min(settings.max_frames),
+            fps: self.fps.unwrap_or(settings.fps),
+            width: self.width.unwrap_or(settings.width).min(settings.max_width),
+            height: self
+                .height
+                .unwrap_or(settings.height)
+                .min(settings.max_height),
+            format: self.format.unwrap_or_default(),
+            overlay: self.overlay.unwrap_or(overlay),
+        };
+        if params.format == TimelapseFormat::Gif {
+            let frame_pixels = (params.width as u64 * params.height as u64).max(1);
+            let max_frames = settings.max_gif_megapixels as u64 * 1_000_000 / frame_pixels;
+            params.frames = params.frames.min(max_frames.max(1) as usize);
+        }
+        if params.frames == 0 || params.width == 0 || params.height == 0 {
+            return Err(Box::from("frames, width and height must be greater than 0"));
+        }
+        if !(1..=100).contains(&params.fps) {
+            return Err(Box::from("fps must be between 1 and 100"));
+        }
+        if let (Some(start), Some(end)) = (params.start, params.end) {
+            if start > end {
+                return Err(Box::from("start must not be after end"));
+            }
+        }
+        Ok(params)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Rendering,
+    Encoding,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimelapseJob {
+    pub id: u64,
+    pub project: u32,
+    pub params: TimelapseParams,
+    pub state: JobState,
+    /* share of the frames loaded, encoding runs at 1 */
+    pub progress: f32,
+    pub frames: usize,
+    pub error: Option<String>,
+    pub created: u64,
+    pub finished: Option<u64>,
+    /* a frame arrived after rendering started, the next request renders again */
+    pub stale: bool,
+}
+
+impl TimelapseJob {
+    fn output(&self) -> PathBuf {
+        config::get().paths.tmp.join(format!(
+            "timelapse-{}-{}.{}",
+            self.project,
+            self.id,
+            self.params.format.extension()
+        ))
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self.state, JobState::Done | JobState::Failed)
+    }
+}
+
+/* finished jobs kept with their output, the oldest are dropped first */
+const MAX_FINISHED_JOBS: usize = 16;
+/* queued and running jobs, each one can take minutes to render */
+const MAX_PENDING_JOBS: usize = 4;
+/* NeuQuant trains the palette on every nth pixel of every nth row */
+const QUANTIZER_SAMPLE_RATE: u32 = 4;
+
+struct Jobs {
+    next_id: u64,
+    jobs: BTreeMap<u64, TimelapseJob>,
+}
+
+lazy_static! {
+    static ref JOBS: Mutex<Jobs> = Mutex::new(Jobs {
+        next_id: 1,
+        jobs: BTreeMap::new(),
+    });
+}
+
+/* returns the job already queued, running or done for the same timelapse, otherwise queues one
+unless MAX_PENDING_JOBS are waiting already */
+pub fn enqueue(
+    project: u32,
+    params: TimelapseParams,
+) -> Result<TimelapseJob, Box<dyn std::error::Error>> {
+    match JOBS.lock() {
+        Ok(mut jobs) => add_job(&mut jobs, project, params),
+        Err(e) => {
+            error!("Error: {}", e);
+            Err(Box::from("Timelapse jobs"))
+        }
+    }
+}
+
+fn add_job(
+    jobs: &mut Jobs,
+    project: u32,
+    params: TimelapseParams,
+) -> Result<TimelapseJob, Box<dyn std::error::Error>> {
+    if let Some(job) = jobs.jobs.values().find(|job| {
+        job.project == project
+            && job.params == params
+            && !job.stale
+            && job.state != JobState::Failed
+    }) {
+        return Ok(job.clone());
+    }
+    if jobs.jobs.values().filter(|job| !job.finished()).count() >= MAX_PENDING_JOBS {
+        return Err(Box::from(format!(
+            "{} timelapses are already waiting to be rendered",
+            MAX_PENDING_JOBS
+        )));
+    }
+    let job = TimelapseJob {
+        id: jobs.next_id,
+        project,
+        params,
+        state: JobState::Queued,
+        progress: 0.0,
+        frames: 0,
+        error: None,
+        created: chrono::Utc::now().timestamp() as u64,
+        finished: None,
+        stale: false,
+    };
+    jobs.next_id += 1;
+    jobs.jobs.insert(job.id, job.clone());
+    info!("queued timelapse job {} for project {}", job.id, project);
+    evict(jobs);
+    Ok(job)
+}
+
+fn evict(jobs: &mut Jobs) {
+    let finished: Vec<u64> = jobs
+        .jobs
+        .values()
+        .filter(|job| job.finished())
+        .map(|job| job.id)
+        .collect();
+    for id in finished
+        .iter()
+        .take(finished.len().saturating_sub(MAX_FINISHED_JOBS))
+    {
+        if let Some(job) = jobs.jobs.remove(id) {
+            let _ = fs::remove_file(job.output());
+        }
+    }
+}
+
+pub fn get_job(id: u64) -> Option<TimelapseJob> {
+    match JOBS.lock() {
+        Ok(jobs) => jobs.jobs.get(&id).cloned(),
+        Err(_) => None,
+    }
+}
+
+/* the rendered file of a finished job */
+pub fn job_output(id: u64) -> Option<PathBuf> {
+    get_job(id)
+        .filter(|job| job.state == JobState::Done)
+        .map(|job| job.output())
+}
+
+/* called by the capture worker, outputs that would include the new frame are out of date */
+pub fn frame_added(project: u32, time: u64) {
+    if let Ok(mut jobs) = JOBS.lock() {
+        for job in jobs.jobs.values_mut() {
+            if job.project == project
+                && job.state != JobState::Queued
+                && job.params.end.is_none_or(|end| time <= end)
+            {
+                job.stale = true;
+            }
+        }
+    }
+}
+
+fn update(id: u64, change: impl FnOnce(&mut TimelapseJob)) {
+    if let Ok(mut jobs) = JOBS.lock() {
+        if let Some(job) = jobs.jobs.get_mut(&id) {
+            change(job);
+        }
+    }
+}
+
+/* takes the oldest queued job, jobs run one at a time on the render worker */
+pub fn start_next() -> Option<TimelapseJob> {
+    let mut jobs = JOBS.lock().ok()?;
+    let job = jobs
+        .jobs
+        .values_mut()
+        .find(|job| job.state == JobState::Queued)?;
+    job.state = JobState::Rendering;
+    Some(job.clone())
+}
+
+pub fn run(job: &TimelapseJob) {
+    let result = render(job);
+    let now = chrono::Utc::now().timestamp() as u64;
+    match result {
+        Ok(_) => {
+            info!("timelapse job {} done", job.id);
+            update(job.id, |job| {
+                job.state = JobState::Done;
+                job.finished = Some(now);
+            });
+        }
+        Err(e) => {
+            error!("Error: timelapse job {}: {}", job.id, e);
+            update(job.id, |job| {
+                job.state = JobState::Failed;
+                job.error = Some(e.to_string());
+                job.finished = Some(now);
+            });
+        }
+    }
+}
+
+/* runs when the render worker (re)starts: jobs it was rendering when it failed are marked failed
+and files no job knows about, e.g. from before a restart, are removed */
+pub fn clean_up() -> Result<(), Box<dyn std::error::Error>> {
+    let outputs: Vec<PathBuf> = match JOBS.lock() {
+        Ok(mut jobs) => jobs
+            .jobs
+            .values_mut()
+            .map(|job| {
+                if matches!(job.state, JobState::Rendering | JobState::Encoding) {
+                    job.state = JobState::Failed;
+                    job.error = Some(String::from("Render worker restarted"));
+                }
+                job.output()
+            })
+            .collect(),
+        Err(e) => {
+            error!("Error: {}", e);
+            return Err(Box::from("Timelapse jobs"));
+        }
+    };
+    let pattern = config::get().paths.tmp.join("timelapse-*");
+    if let Some(pattern) = pattern.to_str() {
+        for path in glob(pattern)? {
+            let path = path?;
+            if !outputs.contains(&path) {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/* evenly spaced frames including the first and the last one */
+fn subsample(frames: Vec<PathBuf>, count: usize) -> Vec<PathBuf> {
+    if frames.len() <= count {
+        return frames;
+    }
+    let last = frames.len() - 1;
+    let steps = (count - 1).max(1);
+    (0..count)
+        .map(|index| frames[index * last / steps].clone())
+        .collect()
+}
+
+/* fits into the requested size keeping the aspect ratio without scaling up, the video encoders
+need even dimensions */
+fn frame_size(width: u32, height: u32, params: &TimelapseParams) -> (u32, u32) {
+    let scale = (params.width as f64 / width as f64)
+        .min(params.height as f64 / height as f64)
+        .min(1.0);
+    let width = ((width as f64 * scale).round() as u32).max(1);
+    let height = ((height as f64 * scale).round() as u32).max(1);
+    match params.format {
+        TimelapseFormat::Gif => (width, height),
+        TimelapseFormat::Mp4 | TimelapseFormat::Webm => ((width & !1).max(2), (height & !1).max(2)),
+    }
+}
+
+/* the first frame decides the size, later ones are scaled to match in case the capture
+resolution changed during the project */
+fn load_frame(
+    path: &Path,
+    size: Option<(u32, u32)>,
+    params: &TimelapseParams,
+) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+    let frame = open_frame(path)?;
+    let (width, height) = size.unwrap_or_else(|| frame_size(frame.width(), frame.height(), params));
+    if (frame.width(), frame.height()) == (width, height) {
+        Ok(frame.into_rgba())
+    } else {
+        Ok(frame
+            .resize_exact(width, height, FilterType::Triangle)
+            .into_rgba())
+    }
+}
+
+/* frames of the requested range, subsampled to the requested count */
+fn job_frames(job: &TimelapseJob) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let params = &job.params;
+    let frames: Vec<PathBuf> = project_frames(job.project)?
+        .into_iter()
+        .filter(|path| match frame_time(path) {
+            Some(time) => {
+                params.start.is_none_or(|start| time >= start)
+                    && params.end.is_none_or(|end| time <= end)
+            }
+            None => false,
+        })
+        .collect();
+    Ok(subsample(frames, params.frames))
+}
+
+/* loads each frame and hands it to `encode`, failing frames are skipped; returns how many
+frames were encoded */
+fn encode_frames(
+    job: &TimelapseJob,
+    frames: &[PathBuf],
+    mut encode: impl FnMut(RgbaImage) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let project = if job.params.overlay {
+        Some(read_project(job.project)?)
+    } else {
+        None
+    };
+    let mut history = SensorHistory::default();
+    let mut size = None;
+    let mut count = 0;
+    for (index, path) in frames.iter().enumerate() {
+        if is_shutting_down() {
+            return Err(Box::from("Shutting down"));
+        }
+        match load_frame(path, size, &job.params) {
+            Ok(mut frame) => {
+                size = Some(frame.dimensions());
+                if let (Some(project), Some(time)) = (project.as_ref(), frame_time(path)) {
+                    let data = OverlayData::recorded(project, time, &mut history);
+                    overlay::draw(&mut frame, &data, config::get().overlay.scale);
+                }
+                encode(frame)?;
+                count += 1;
+            }
+            /* the newest frame may still be written by the capture worker */
+            Err(e) => warn!("skipping frame {}: {}", path.display(), e),
+        }
+        update(job.id, |job| {
+            job.progress = (index + 1) as f32 / frames.len() as f32;
+        });
+    }
+    if count == 0 {
+        return Err(Box::from(format!("No frames for project {}", job.project)));
+    }
+    update(job.id, |job| {
+        job.state = JobState::Encoding;
+        job.progress = 1.0;
+        job.frames = count;
+    });
+    Ok(count)
+}
+
+/* the palette is computed over all frames, so they are kept in memory until encoding */
+fn render_gif(
+    job: &TimelapseJob,
+    frames: &[PathBuf],
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut images = Vec::with_capacity(frames.len());
+    encode_frames(job, frames, |frame| {
+        images.push(engiffen::Image {
+            width: frame.width(),
+            height: frame.height(),
+            pixels: frame.pixels().map(|pixel| pixel.0).collect(),
+        });
+        Ok(())
+    })?;
+    let gif = engiffen(
+        &images,
+        job.params.fps as usize,
+        Quantizer::NeuQuant(QUANTIZER_SAMPLE_RATE),
+    )?;
+    drop(images);
+    let mut writer = BufWriter::new(File::create(output)?);
+    gif.write(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/* ffmpeg reading raw RGBA frames from stdin; it is killed if dropped before finishing */
+struct VideoEncoder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    log: Option<JoinHandle<String>>,
+}
+
+impl VideoEncoder {
+    fn spawn(
+        params: &TimelapseParams,
+        (width, height): (u32, u32),
+        output: &Path,
+    ) -> Result<VideoEncoder, Box<dyn std::error::Error>> {
+        let mut command = Command::new(&config::get().timelapse.ffmpeg);
+        command
+            .args(["-y", "-nostdin", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+            .arg("-s")
+            .arg(format!("{}x{}", width, height))
+            .arg("-r")
+            .arg(params.fps.to_string())
+            .args(["-i", "pipe:0"]);
+        match params.format {
+            TimelapseFormat::Mp4 => command.args([
+                "-c:v",
+                "libx264",
+                "-preset",
+                "veryfast",
+                "-crf",
+                "23",
+                "-pix_fmt",
+                "yuv420p",
+                "-movflags",
+                "+faststart",
+                "-f",
+                "mp4",
+            ]),
+            TimelapseFormat::Webm => command.args([
+                "-c:v",
+                "libvpx-vp9",
+                "-crf",
+                "32",
+                "-b:v",
+                "0",
+                "-row-mt",
+                "1",
+                "-pix_fmt",
+                "yuv420p",
+                "-f",
+                "webm",
+            ]),
+            TimelapseFormat::Gif => return Err(Box::from("GIFs are not encoded by ffmpeg")),
+        };
+        let mut child = command
+            .arg(output)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        /* drained on its own thread, a full stderr pipe would block ffmpeg and with it us */
+        let log = child.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut log = String::new();
+                let _ = stderr.read_to_string(&mut log);
+                log
+            })
+        });
+        Ok(VideoEncoder { child, stdin, log })
+    }
+
+    fn write(&mut self, frame: &RgbaImage) -> Result<(), Box<dyn std::error::Error>> {
+        let result = match self.stdin.as_mut() {
+            Some(stdin) => stdin.write_all(frame.as_raw()),
+            None => return Err(Box::from("ffmpeg input closed")),
+        };
+        match result {
+            Ok(_) => Ok(()),
+            /* ffmpeg quit early, its log says why */
+            Err(e) => Err(self.finish().err().unwrap_or_else(|| Box::from(e))),
+        }
+    }
+
+    /* closes the input and waits for ffmpeg to write the rest of the file */
+    fn finish(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        let log = match self.log.take() {
+            Some(log) => log.join().unwrap_or_default(),
+            None => String::new(),
+        };
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Box::from(format!("ffmpeg {}: {}", status, log.trim())))
+        }
+    }
+}
+
+impl Drop for VideoEncoder {
+    fn drop(&mut self) {
+        if self.stdin.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/* frames are streamed to ffmpeg as they are loaded, ffmpeg starts with the first frame's size */
+fn render_video(
+    job: &TimelapseJob,
+    frames: &[PathBuf],
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut encoder: Option<VideoEncoder> = None;
+    encode_frames(job, frames, |frame| {
+        let encoder = match encoder.as_mut() {
+            Some(encoder) => encoder,
+            None => encoder.insert(VideoEncoder::spawn(
+                &job.params,
+                frame.dimensions(),
+                output,
+            )?),
+        };
+        encoder.write(&frame)
+    })?;
+    match encoder.as_mut() {
+        Some(encoder) => encoder.finish(),
+        None => Err(Box::from("No frames encoded")),
+    }
+}
+
+/* the output is only replaced once it is complete so readers never see a partial file */
+fn render(job: &TimelapseJob) -> Result<(), Box<dyn std::error::Error>> {
+    let frames = job_frames(job)?;
+    let output = job.output();
+    if let Some(dir) = output.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = output.with_extension("part");
+    let result = match job.params.format {
+        TimelapseFormat::Gif => render_gif(job, &frames, &partial),
+        TimelapseFormat::Mp4 | TimelapseFormat::Webm => render_video(job, &frames, &partial),
+    };
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, &output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(
+        frames: usize,
+        width: u32,
+        height: u32,
+        format: TimelapseFormat,
+    ) -> TimelapseRequest {
+        TimelapseRequest {
+            frames: Some(frames),
+            width: Some(width),
+            height: Some(height),
+            format: Some(format),
+            ..TimelapseRequest::default()
+        }
+    }
+
+    #[test]
+    fn defaults_come_from_the_settings() {
+        let settings = TimelapseSettings::default();
+        let params = TimelapseRequest::default()
+            .resolve_with(&settings, true)
+            .unwrap();
+        assert_eq!(params.frames, settings.frames);
+        assert_eq!((params.width, params.height), (640, 480));
+        assert_eq!(params.format, TimelapseFormat::Gif);
+        assert!(params.overlay);
+    }
+
+    #[test]
+    fn oversized_requests_are_clamped() {
+        let settings = TimelapseSettings::default();
+        let params = request(1_000_000, 100_000, 100_000, TimelapseFormat::Mp4)
+            .resolve_with(&settings, false)
+            .unwrap();
+        assert_eq!(params.frames, settings.max_frames);
+        assert_eq!((params.width, params.height), (1920, 1080));
+    }
+
+    #[test]
+    fn gif_frames_are_limited_by_total_pixels() {
+        let settings = TimelapseSettings::default();
+        let params = request(1000, 1920, 1080, TimelapseFormat::Gif)
+            .resolve_with(&settings, false)
+            .unwrap();
+        assert_eq!(params.frames, 48);
+        let params = request(100, 640, 480, TimelapseFormat::Gif)
+            .resolve_with(&settings, false)
+            .unwrap();
+        assert_eq!(params.frames, 100);
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let settings = TimelapseSettings::default();
+        assert!(request(0, 640, 480, TimelapseFormat::Gif)
+            .resolve_with(&settings, false)
+            .is_err());
+        let reversed = TimelapseRequest {
+            start: Some(200),
+            end: Some(100),
+            ..TimelapseRequest::default()
+        };
+        assert!(reversed.resolve_with(&settings, false).is_err());
+    }
+
+    #[test]
+    fn subsample_keeps_first_and_last_frame() {
+        let frames: Vec<PathBuf> = (0..10)
+            .map(|i| PathBuf::from(format!("{}.jpg", i)))
+            .collect();
+        let picked = subsample(frames.clone(), 4);
+        assert_eq!(
+            picked,
+            ["0.jpg", "3.jpg", "6.jpg", "9.jpg"]
+                .map(PathBuf::from)
+                .to_vec()
+        );
+        assert_eq!(subsample(frames.clone(), 20), frames);
+    }
+
+    #[test]
+    fn videos_get_even_dimensions_without_upscaling() {
+        let params = request(10, 641, 481, TimelapseFormat::Mp4)
+            .resolve_with(&TimelapseSettings::default(), false)
+            .unwrap();
+        assert_eq!(frame_size(1280, 720, &params), (640, 360));
+        assert_eq!(frame_size(321, 241, &params), (320, 240));
+    }
+
+    #[test]
+    fn pending_jobs_are_limited() {
+        let mut jobs = Jobs {
+            next_id: 1,
+            jobs: BTreeMap::new(),
+        };
+        let params = |fps| TimelapseParams {
+            fps,
+            ..TimelapseRequest::default().resolve().unwrap()
+        };
+        for fps in 1..=MAX_PENDING_JOBS as u32 {
+            add_job(&mut jobs, 1, params(fps)).unwrap();
+        }
+        assert!(add_job(&mut jobs, 1, params(30)).is_err());
+        /* a request for a queued timelapse still gets its job */
+        assert_eq!(add_job(&mut jobs, 1, params(1)).unwrap().id, 1);
+        if let Some(job) = jobs.jobs.get_mut(&1) {
+            job.state = JobState::Done;
+        }
+        assert!(add_job(&mut jobs, 1, params(30)).is_ok());
+    }
+}
@@ -1,22 +1,487 @@
-use std::{env, path::Path};
+use lazy_static::lazy_static;
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use glob::glob;
+use image::{imageops::FilterType, jpeg::JpegEncoder, ColorType, DynamicImage, RgbImage};
+use nokhwa::{CameraFormat, FrameFormat};
+use rocket::tokio::sync::broadcast;
+use serde::{Deserialize, Serialize};
 
-use rocket::fs::NamedFile;
-use std::process::Command;
-
-pub async fn generate_gif(project: u32) -> Result<NamedFile, Box<dyn std::error::Error>> {
-    let dir = env::current_dir()?;
-    let root = dir.to_str().unwrap();
-    let files = glob(format!("./webcam/{project}/*.png").as_str())?;
-    let mut command = Command::new("gifski");
-    command.arg("-o").arg(format!("{}/tmp/output.gif", root));
-    for path in files {
-        let path = path?;
-        let path = path.to_str().unwrap();
-        command.arg(format!("{}", path));
-    }
-    //let fps = files.count() / 30;
-    command.spawn()?;
-    Ok(NamedFile::open(Path::new("tmp/output.gif")).await?)
+use super::config::{self, seconds};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureFormat {
+    Mjpeg,
+    Yuyv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureSettings {
+    #[serde(with = "seconds")]
+    pub interval: Duration,
+    pub width: u32,
+    pub height: u32,
+    pub format: CaptureFormat,
+    pub fps: u32,
+    pub stream: StreamSettings,
+    pub storage: StorageSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageFormat {
+    Png,
+    Jpeg,
+    /* encoded by ffmpeg, it needs to be built with libwebp */
+    Webp,
+    /* the JPEG as sent by the camera, without decoding and encoding it again */
+    Mjpeg,
+}
+
+impl StorageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StorageFormat::Png => "png",
+            StorageFormat::Jpeg | StorageFormat::Mjpeg => "jpg",
+            StorageFormat::Webp => "webp",
+        }
+    }
+}
+
+/* extensions of stored frames, older versions only wrote PNG */
+const FRAME_EXTENSIONS: [&str; 3] = ["png", "jpg", "webp"];
+
+/* how frames are written to disk, one directory per UTC day */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub format: StorageFormat,
+    /* JPEG and WebP quality from 1 to 100 */
+    pub quality: u8,
+    /* larger frames are scaled down to fit, keeping their aspect ratio */
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            format: StorageFormat::Jpeg,
+            quality: 85,
+            max_width: None,
+            max_height: None,
+        }
+    }
+}
+
+/* the live stream is scaled down from the capture resolution */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamSettings {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    /* JPEG quality from 1 to 100 */
+    pub quality: u8,
+}
+
+impl Default for StreamSettings {
+    fn default() -> Self {
+        StreamSettings {
+            width: 640,
+            height: 360,
+            fps: 5,
+            quality: 70,
+        }
+    }
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        CaptureSettings {
+            interval: Duration::from_secs(1),
+            width: 1920,
+            height: 1080,
+            format: CaptureFormat::Mjpeg,
+            fps: 30,
+            stream: StreamSettings::default(),
+            storage: StorageSettings::default(),
+        }
+    }
+}
+
+pub type SnapshotResult = Result<PathBuf, String>;
+
+/* viewers that fall this many frames behind skip ahead to the newest one */
+const STREAM_BUFFER: usize = 2;
+
+lazy_static! {
+    static ref SNAPSHOT_REQUESTS: Mutex<Vec<Sender<SnapshotResult>>> = Mutex::new(Vec::new());
+    static ref STREAM: broadcast::Sender<Arc<Vec<u8>>> = broadcast::channel(STREAM_BUFFER).0;
+}
+
+/* the capture worker owns the camera, it answers on the receiver once the frame is stored */
+pub fn request_snapshot() -> Result<Receiver<SnapshotResult>, Box<dyn std::error::Error>> {
+    let (sender, receiver) = channel();
+    match SNAPSHOT_REQUESTS.lock() {
+        Ok(mut requests) => requests.push(sender),
+        Err(e) => {
+            error!("Error: {}", e);
+            return Err(Box::from("Snapshot requests"));
+        }
+    }
+    Ok(receiver)
+}
+
+pub fn has_snapshot_request() -> bool {
+    match SNAPSHOT_REQUESTS.lock() {
+        Ok(requests) => !requests.is_empty(),
+        Err(_) => false,
+    }
+}
+
+pub fn take_snapshot_requests() -> Vec<Sender<SnapshotResult>> {
+    match SNAPSHOT_REQUESTS.lock() {
+        Ok(mut requests) => requests.drain(..).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/* every viewer gets its own receiver, the capture worker only streams while there is one */
+pub fn subscribe_stream() -> broadcast::Receiver<Arc<Vec<u8>>> {
+    STREAM.subscribe()
+}
+
+pub fn has_stream_viewers() -> bool {
+    STREAM.receiver_count() > 0
+}
+
+/* scale the frame down to the stream size and hand it to all viewers as JPEG */
+pub fn publish_stream_frame(frame: &RgbImage) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = &get_capture_settings().stream;
+    let mut jpeg = Vec::new();
+    let mut encoder = JpegEncoder::new_with_quality(&mut jpeg, settings.quality);
+    if frame.width() > settings.width || frame.height() > settings.height {
+        let scale = (settings.width as f64 / frame.width() as f64)
+            .min(settings.height as f64 / frame.height() as f64);
+        let resized = image::imageops::resize(
+            frame,
+            ((frame.width() as f64 * scale).round() as u32).max(1),
+            ((frame.height() as f64 * scale).round() as u32).max(1),
+            FilterType::Triangle,
+        );
+        encoder.encode_image(&resized)?;
+    } else {
+        encoder.encode_image(frame)?;
+    }
+    /* fails only when the last viewer left in the meantime */
+    let _ = STREAM.send(Arc::new(jpeg));
+    Ok(())
+}
+
+pub fn get_capture_settings() -> &'static CaptureSettings {
+    &config::get().webcam
+}
+
+pub fn camera_format(settings: &CaptureSettings) -> CameraFormat {
+    CameraFormat::new_from(
+        settings.width,
+        settings.height,
+        match settings.format {
+            CaptureFormat::Mjpeg => FrameFormat::MJPEG,
+            CaptureFormat::Yuyv => FrameFormat::YUYV,
+        },
+        settings.fps,
+    )
+}
+
+/* capture time of a frame, taken from its file name */
+pub fn frame_time(path: &Path) -> Option<u64> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.parse().ok())
+}
+
+fn is_frame(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    extension.is_some_and(|extension| FRAME_EXTENSIONS.contains(&extension))
+        && frame_time(path).is_some()
+}
+
+fn day_dir(project: u32, time: u64) -> PathBuf {
+    let day = chrono::DateTime::from_timestamp(time as i64, 0).unwrap_or_default();
+    config::get()
+        .paths
+        .webcam_dir(project)
+        .join(day.format("%Y-%m-%d").to_string())
+}
+
+/* all frames of a project, oldest first; older frames lie directly in the project directory */
+pub fn project_frames(project: u32) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let dir = config::get().paths.webcam_dir(project);
+    let mut frames = Vec::new();
+    for pattern in [dir.join("*"), dir.join("*").join("*")] {
+        let pattern = match pattern.to_str() {
+            Some(pattern) => pattern.to_string(),
+            None => return Err(Box::from("Invalid webcam path")),
+        };
+        for path in glob(&pattern)? {
+            let path = path?;
+            if is_frame(&path) {
+                frames.push(path);
+            }
+        }
+    }
+    frames.sort_by_key(|path| frame_time(path));
+    Ok(frames)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FramePage {
+    pub total: usize,
+    pub offset: usize,
+    /* capture times, oldest first */
+    pub frames: Vec<u64>,
+}
+
+pub fn frame_page(
+    project: u32,
+    offset: usize,
+    limit: usize,
+) -> Result<FramePage, Box<dyn std::error::Error>> {
+    let frames = project_frames(project)?;
+    Ok(FramePage {
+        total: frames.len(),
+        offset,
+        frames: frames
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .filter_map(|path| frame_time(path))
+            .collect(),
+    })
+}
+
+pub fn frame_path(project: u32, time: u64) -> Option<PathBuf> {
+    let dirs = [
+        day_dir(project, time),
+        config::get().paths.webcam_dir(project),
+    ];
+    dirs.iter()
+        .flat_map(|dir| {
+            FRAME_EXTENSIONS
+                .iter()
+                .map(move |extension| dir.join(format!("{}.{}", time, extension)))
+        })
+        .find(|path| path.is_file())
+}
+
+/* store a captured frame, `mjpeg` holds the camera's own JPEG for StorageFormat::Mjpeg. The
+frame is only moved into place once it is complete so readers never see a partial file */
+pub fn store_frame(
+    project: u32,
+    time: u64,
+    frame: &RgbImage,
+    mjpeg: Option<&[u8]>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let settings = &get_capture_settings().storage;
+    let dir = day_dir(project, time);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.{}", time, settings.format.extension()));
+    let partial = path.with_extension("part");
+    if let Err(e) = write_frame(&partial, frame, mjpeg, settings) {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+fn write_frame(
+    path: &Path,
+    frame: &RgbImage,
+    mjpeg: Option<&[u8]>,
+    settings: &StorageSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let (StorageFormat::Mjpeg, Some(mjpeg)) = (settings.format, mjpeg) {
+        fs::write(path, mjpeg)?;
+        return Ok(());
+    }
+    let resized = storage_size(frame.width(), frame.height(), settings)
+        .map(|(width, height)| image::imageops::resize(frame, width, height, FilterType::Triangle));
+    let frame = resized.as_ref().unwrap_or(frame);
+    match settings.format {
+        StorageFormat::Png => frame.save_with_format(path, image::ImageFormat::Png)?,
+        StorageFormat::Jpeg | StorageFormat::Mjpeg => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            JpegEncoder::new_with_quality(&mut writer, settings.quality).encode(
+                frame,
+                frame.width(),
+                frame.height(),
+                ColorType::Rgb8,
+            )?;
+            writer.flush()?;
+        }
+        StorageFormat::Webp => encode_webp(frame, path, settings.quality)?,
+    }
+    Ok(())
+}
+
+/* the size to scale the frame down to, None when it already fits */
+fn storage_size(width: u32, height: u32, settings: &StorageSettings) -> Option<(u32, u32)> {
+    let scale = (settings.max_width.unwrap_or(width) as f64 / width as f64)
+        .min(settings.max_height.unwrap_or(height) as f64 / height as f64);
+    if scale >= 1.0 {
+        return None;
+    }
+    Some((
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    ))
+}
+
+/* decode a stored frame; the WebP decoder of image 0.23 only returns the luma channel, so WebP
+frames are converted by ffmpeg like they are encoded */
+pub fn open_frame(path: &Path) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    if path.extension().and_then(|extension| extension.to_str()) != Some("webp") {
+        return Ok(image::open(path)?);
+    }
+    let output = Command::new(&config::get().timelapse.ffmpeg)
+        .args(["-nostdin", "-loglevel", "error", "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        return Err(Box::from(format!(
+            "ffmpeg failed to decode WebP: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(image::load_from_memory_with_format(
+        &output.stdout,
+        image::ImageFormat::Png,
+    )?)
+}
+
+/* the image crate only decodes WebP, so the frame is piped through ffmpeg */
+fn encode_webp(
+    frame: &RgbImage,
+    path: &Path,
+    quality: u8,
+) -> Result<(), Box<dyn std::error::Error>> {
+    encode_webp_with(&config::get().timelapse.ffmpeg, frame, path, quality)
+}
+
+fn encode_webp_with(
+    ffmpeg: &Path,
+    frame: &RgbImage,
+    path: &Path,
+    quality: u8,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new(ffmpeg)
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgb24",
+        ])
+        .args(["-s", &format!("{}x{}", frame.width(), frame.height())])
+        .args(["-i", "-", "-frames:v", "1", "-c:v", "libwebp"])
+        .args(["-quality", &quality.to_string()])
+        /* the path may not end in .webp */
+        .args(["-f", "webp"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    /* ffmpeg without libwebp quits before reading its input, it is waited for either way and
+    its log says why */
+    let written = match child.stdin.take() {
+        Some(mut stdin) => stdin.write_all(frame.as_raw()),
+        None => Ok(()),
+    };
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let _ = fs::remove_file(path);
+        return Err(Box::from(format!(
+            "ffmpeg failed to encode WebP: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    written?;
+    Ok(())
+}
+
+pub fn latest_frame(project: u32) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    Ok(project_frames(project)?.pop())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    /* needs an ffmpeg with libwebp on the PATH, skipped without one */
+    #[test]
+    fn webp_frames_keep_their_colour() {
+        if Command::new(&config::get().timelapse.ffmpeg)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .status()
+            .is_err()
+        {
+            eprintln!("ffmpeg not found, skipping");
+            return;
+        }
+        let frame = RgbImage::from_fn(64, 32, |x, _| {
+            if x < 32 {
+                Rgb([200, 30, 30])
+            } else {
+                Rgb([30, 160, 60])
+            }
+        });
+        let path = std::env::temp_dir().join(format!("webp-{}.webp", std::process::id()));
+        encode_webp(&frame, &path, 90).unwrap();
+        let decoded = open_frame(&path);
+        let _ = fs::remove_file(&path);
+        let decoded = decoded.unwrap().into_rgb();
+        assert_eq!(decoded.dimensions(), (64, 32));
+        for (x, expected) in [(8, frame.get_pixel(8, 16)), (56, frame.get_pixel(56, 16))] {
+            let pixel = decoded.get_pixel(x, 16);
+            for channel in 0..3 {
+                assert!((pixel[channel] as i32 - expected[channel] as i32).abs() < 24);
+            }
+        }
+    }
+
+    #[test]
+    fn failed_webp_encoder_is_reaped_and_reported() {
+        /* `false` stands in for an ffmpeg that quits before reading the frame */
+        let frame = RgbImage::new(1920, 1080);
+        let path = std::env::temp_dir().join(format!("webp-{}.part", std::process::id()));
+        let result = encode_webp_with(Path::new("false"), &frame, &path, 90);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("ffmpeg failed to encode WebP"));
+    }
 }